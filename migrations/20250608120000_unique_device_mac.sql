@@ -0,0 +1,46 @@
+-- Migration: Enforce one device row per MAC address
+-- Normalize existing addresses to the upper-case, colon separated form,
+-- whether they were stored with colons, dashes or as bare hex
+UPDATE devices
+SET
+    mac_address = UPPER(REPLACE(TRIM(mac_address), '-', ':'));
+
+UPDATE devices
+SET
+    mac_address = SUBSTR(mac_address, 1, 2) || ':' || SUBSTR(mac_address, 3, 2) || ':' || SUBSTR(mac_address, 5, 2) || ':' || SUBSTR(mac_address, 7, 2) || ':' || SUBSTR(mac_address, 9, 2) || ':' || SUBSTR(mac_address, 11, 2)
+WHERE
+    LENGTH(mac_address) = 12;
+
+-- Keep the oldest registration when duplicates exist, moving the playlists
+-- of the others over to it so nothing is lost to the cascade
+UPDATE playlists
+SET
+    device_id = (
+        SELECT
+            MIN(survivor.id)
+        FROM
+            devices AS survivor
+            JOIN devices AS duplicate ON duplicate.mac_address = survivor.mac_address
+        WHERE
+            duplicate.id = playlists.device_id
+    )
+WHERE
+    device_id IN (
+        SELECT
+            id
+        FROM
+            devices
+    );
+
+DELETE FROM devices
+WHERE
+    id NOT IN (
+        SELECT
+            MIN(id)
+        FROM
+            devices
+        GROUP BY
+            mac_address
+    );
+
+CREATE UNIQUE INDEX idx_devices_mac_address ON devices (mac_address);
@@ -0,0 +1,6 @@
+-- Migration: Additional telemetry reported by the firmware on check-in
+ALTER TABLE devices ADD COLUMN model TEXT;
+
+ALTER TABLE devices ADD COLUMN last_refresh_rate INTEGER;
+
+ALTER TABLE devices ADD COLUMN last_seen_at TEXT;
@@ -0,0 +1,2 @@
+-- Migration: Credentials used when proxying a device to the TRMNL cloud
+ALTER TABLE devices ADD COLUMN cloud_api_key TEXT;
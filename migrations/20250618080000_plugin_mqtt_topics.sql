@@ -0,0 +1,2 @@
+-- Migration: Topic filters for plugins using the "mqtt" data strategy
+ALTER TABLE plugins ADD COLUMN mqtt_topics TEXT;
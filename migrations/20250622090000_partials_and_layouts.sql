@@ -0,0 +1,11 @@
+-- Migration: Shared Liquid partials and per-plugin layouts
+CREATE TABLE partials (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    markup TEXT NOT NULL,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Name of a layout in templates/layouts, NULL for templates/base.liquid
+ALTER TABLE plugins ADD COLUMN layout TEXT;
@@ -0,0 +1,28 @@
+-- Migration: Markup for each TRMNL view size, and mashups that arrange
+-- several plugins on one screen
+
+-- Fall back to render_markup when empty
+ALTER TABLE plugins ADD COLUMN markup_half_horizontal TEXT;
+ALTER TABLE plugins ADD COLUMN markup_half_vertical TEXT;
+ALTER TABLE plugins ADD COLUMN markup_quadrant TEXT;
+
+CREATE TABLE mashups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    -- TRMNL mashup arrangement, e.g. 1Lx1R or 2x2
+    layout TEXT NOT NULL,
+    current_image TEXT,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE mashup_plugins (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mashup_id INTEGER NOT NULL,
+    plugin_id INTEGER NOT NULL,
+    -- Slot in the arrangement, from 0
+    position INTEGER NOT NULL,
+    FOREIGN KEY (mashup_id) REFERENCES mashups (id) ON DELETE CASCADE,
+    FOREIGN KEY (plugin_id) REFERENCES plugins (id) ON DELETE CASCADE,
+    UNIQUE (mashup_id, position)
+);
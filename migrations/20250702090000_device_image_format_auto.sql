@@ -0,0 +1,4 @@
+-- Migration: image_format used to be stored but ignored, with the format
+-- picked from the firmware version. Keep that behaviour for existing devices
+-- now that png and bmp are honored as overrides.
+UPDATE devices SET image_format = 'auto' WHERE image_format = 'png';
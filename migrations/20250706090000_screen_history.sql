@@ -0,0 +1,20 @@
+-- Migration: What each device was shown and when. A row covers the stretch
+-- of check-ins that received the same image.
+CREATE TABLE screen_history (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    device_id INTEGER NOT NULL,
+    -- As sent in the display response, e.g. <id>.png or setup-logo.bmp
+    filename TEXT NOT NULL,
+    -- Where the image came from, when it was rendered here
+    plugin_id INTEGER,
+    mashup_id INTEGER,
+    -- Name of the plugin or mashup at the time, kept after it is deleted
+    source TEXT,
+    shown_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_shown_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (device_id) REFERENCES devices (id) ON DELETE CASCADE,
+    FOREIGN KEY (plugin_id) REFERENCES plugins (id) ON DELETE SET NULL,
+    FOREIGN KEY (mashup_id) REFERENCES mashups (id) ON DELETE SET NULL
+);
+
+CREATE INDEX screen_history_device_shown_at ON screen_history (device_id, shown_at);
@@ -0,0 +1,3 @@
+-- Migration: Remember screen sizes set through the API or admin so the
+-- width and height reported on check-in no longer replace them
+ALTER TABLE devices ADD COLUMN custom_geometry BOOLEAN NOT NULL DEFAULT FALSE;
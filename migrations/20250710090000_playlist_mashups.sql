@@ -0,0 +1,35 @@
+-- Migration: Let playlist items show a mashup instead of a single plugin.
+-- SQLite cannot relax NOT NULL in place, so the table is rebuilt.
+CREATE TABLE playlist_items_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    playlist_id INTEGER NOT NULL,
+    plugin_id INTEGER,
+    mashup_id INTEGER,
+    order_index INTEGER DEFAULT 0,
+    is_active BOOLEAN DEFAULT TRUE,
+    last_displayed_at DATETIME,
+    created_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (playlist_id) REFERENCES playlists (id) ON DELETE CASCADE,
+    FOREIGN KEY (plugin_id) REFERENCES plugins (id) ON DELETE CASCADE,
+    FOREIGN KEY (mashup_id) REFERENCES mashups (id) ON DELETE CASCADE,
+    -- Exactly one of the two
+    CHECK ((plugin_id IS NULL) <> (mashup_id IS NULL))
+);
+
+INSERT INTO playlist_items_new (id, playlist_id, plugin_id, order_index, is_active, last_displayed_at, created_at, updated_at)
+SELECT
+    id,
+    playlist_id,
+    plugin_id,
+    order_index,
+    is_active,
+    last_displayed_at,
+    created_at,
+    updated_at
+FROM
+    playlist_items;
+
+DROP TABLE playlist_items;
+
+ALTER TABLE playlist_items_new RENAME TO playlist_items;
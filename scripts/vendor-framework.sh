@@ -0,0 +1,35 @@
+#!/bin/sh
+# Maintainer tool: download the TRMNL framework and Inter font at the pinned
+# versions below into assets/framework. Nothing at build, deploy or render
+# time runs this. Run it from the repository root on a connected machine,
+# review SHA256SUMS, commit everything under assets/framework and then point
+# the links in templates/base.liquid at framework/ so screens render without
+# network access.
+set -eu
+
+FRAMEWORK_VERSION="2.1.0"
+INTER_VERSION="5.2.5"
+
+FRAMEWORK_URL="https://usetrmnl.com"
+FONTSOURCE_URL="https://cdn.jsdelivr.net/npm/@fontsource/inter@$INTER_VERSION"
+DEST="${1:-assets/framework}"
+
+mkdir -p "$DEST/fonts"
+curl -fsSL "$FRAMEWORK_URL/css/$FRAMEWORK_VERSION/plugins.css" -o "$DEST/plugins.css"
+curl -fsSL "$FRAMEWORK_URL/js/$FRAMEWORK_VERSION/plugins.js" -o "$DEST/plugins.js"
+for weight in 300 400 500; do
+    curl -fsSL "$FONTSOURCE_URL/files/inter-latin-$weight-normal.woff2" \
+        -o "$DEST/fonts/inter-latin-$weight-normal.woff2"
+done
+curl -fsSL "$FONTSOURCE_URL/LICENSE" -o "$DEST/fonts/LICENSE"
+
+if grep -Eq 'url\((["'"'"']?)https?://' "$DEST/plugins.css"; then
+    echo "warning: $DEST/plugins.css still references remote URLs:" >&2
+    grep -Eo 'url\((["'"'"']?)https?://[^)]*\)' "$DEST/plugins.css" | sort -u >&2
+fi
+
+(
+    cd "$DEST"
+    echo "# TRMNL framework $FRAMEWORK_VERSION, Inter $INTER_VERSION (@fontsource/inter)"
+    sha256sum plugins.css plugins.js fonts/*.woff2 fonts/LICENSE
+) >"$DEST/SHA256SUMS"
@@ -0,0 +1,944 @@
+use std::{fmt, time::Duration};
+
+use axum::{
+    Form, Router,
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, Redirect},
+    routing::{get, post},
+};
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+use crate::{
+    api::devices::DeviceResponse,
+    models::{
+        device::Device,
+        mashup::{Mashup, MashupLayout, MashupParams},
+        partial::{Partial, PartialParams},
+        playlist::{Playlist, PlaylistParams},
+        plugin::{Plugin, PluginParams},
+        state::AppState,
+    },
+    render::{
+        image::{RenderedImage, ScreenFormat},
+        template::{DEFAULT_LAYOUT, layout_names, render_template_file},
+    },
+};
+
+/// Raw plugin form submission. Browsers send blank inputs as empty strings.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PluginForm {
+    pub name: String,
+    pub data_strategy: String,
+    pub data_payload: String,
+    pub data_stale_minutes: String,
+    pub polling_url: String,
+    pub polling_verb: String,
+    pub polling_header: String,
+    pub render_markup: String,
+    pub mqtt_topics: String,
+    pub layout: String,
+    pub markup_half_horizontal: String,
+    pub markup_half_vertical: String,
+    pub markup_quadrant: String,
+}
+
+impl TryFrom<PluginForm> for PluginParams {
+    type Error = StatusCode;
+
+    fn try_from(form: PluginForm) -> Result<Self, Self::Error> {
+        let name = form.name.trim().to_string();
+        if name.is_empty() {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        let data_payload = non_empty(form.data_payload);
+        if let Some(payload) = &data_payload
+            && serde_json::from_str::<Value>(payload).is_err()
+        {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        Ok(PluginParams {
+            name,
+            data_strategy: non_empty(form.data_strategy),
+            data_payload,
+            data_stale_minutes: parse_optional(form.data_stale_minutes)?,
+            polling_url: non_empty(form.polling_url),
+            polling_verb: non_empty(form.polling_verb),
+            polling_header: non_empty(form.polling_header),
+            render_markup: non_empty(form.render_markup),
+            mqtt_topics: non_empty(form.mqtt_topics),
+            layout: non_empty(form.layout).filter(|layout| layout != DEFAULT_LAYOUT),
+            markup_half_horizontal: non_empty(form.markup_half_horizontal),
+            markup_half_vertical: non_empty(form.markup_half_vertical),
+            markup_quadrant: non_empty(form.markup_quadrant),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PartialForm {
+    pub name: String,
+    #[serde(default)]
+    pub markup: String,
+}
+
+impl TryFrom<PartialForm> for PartialParams {
+    type Error = StatusCode;
+
+    fn try_from(form: PartialForm) -> Result<Self, Self::Error> {
+        let name = form.name.trim().to_string();
+        if !Partial::valid_name(&name) {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        Ok(PartialParams {
+            name,
+            markup: form.markup,
+        })
+    }
+}
+
+/// Mashup form submission, one select per slot. Unused slots are blank.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MashupForm {
+    pub name: String,
+    pub layout: String,
+    pub plugin_1: String,
+    pub plugin_2: String,
+    pub plugin_3: String,
+    pub plugin_4: String,
+}
+
+impl TryFrom<MashupForm> for MashupParams {
+    type Error = StatusCode;
+
+    fn try_from(form: MashupForm) -> Result<Self, Self::Error> {
+        let layout: MashupLayout = form.layout.parse().map_err(unprocessable)?;
+        let plugin_ids = [form.plugin_1, form.plugin_2, form.plugin_3, form.plugin_4]
+            .into_iter()
+            .filter_map(|id| parse_optional(id).transpose())
+            .collect::<Result<Vec<i64>, _>>()?;
+        let params = MashupParams {
+            name: form.name.trim().to_string(),
+            layout,
+            plugin_ids,
+        };
+        params.validate().map_err(unprocessable)?;
+        Ok(params)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PlaylistForm {
+    pub device_id: i64,
+    pub name: String,
+    #[serde(default)]
+    pub weekdays: String,
+    #[serde(default)]
+    pub active_from: String,
+    #[serde(default)]
+    pub active_until: String,
+    #[serde(default)]
+    pub refresh_time: String,
+}
+
+impl TryFrom<PlaylistForm> for PlaylistParams {
+    type Error = StatusCode;
+
+    fn try_from(form: PlaylistForm) -> Result<Self, Self::Error> {
+        let name = form.name.trim().to_string();
+        if name.is_empty() {
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        Ok(PlaylistParams {
+            device_id: form.device_id,
+            name,
+            weekdays: non_empty(form.weekdays),
+            active_from: non_empty(form.active_from),
+            active_until: non_empty(form.active_until),
+            refresh_time: parse_optional(form.refresh_time)?,
+        })
+    }
+}
+
+/// Posted by either the plugin or the mashup select of a playlist
+#[derive(Deserialize, Debug)]
+pub struct PlaylistItemForm {
+    pub plugin_id: Option<i64>,
+    pub mashup_id: Option<i64>,
+}
+
+/// Log why a page failed before answering with a bare 500
+fn internal_error(err: impl fmt::Display) -> StatusCode {
+    warn!("admin request failed: {:#}", err);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// Log why a submission was refused, e.g. a duplicate name, before
+/// answering with a bare 422
+fn unprocessable(err: impl fmt::Display) -> StatusCode {
+    debug!("admin submission refused: {:#}", err);
+    StatusCode::UNPROCESSABLE_ENTITY
+}
+
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+fn parse_optional<T: std::str::FromStr>(value: String) -> Result<Option<T>, StatusCode> {
+    non_empty(value)
+        .map(|v| v.parse().map_err(|_| StatusCode::UNPROCESSABLE_ENTITY))
+        .transpose()
+}
+
+/// Render an admin page template inside the shared admin layout
+fn page(
+    state: &AppState,
+    title: &str,
+    template: &str,
+    data: Value,
+) -> Result<Html<String>, StatusCode> {
+    let admin_dir = state.config.paths.templates_dir.join("admin");
+    let content = render_template_file(&admin_dir.join(format!("{}.liquid", template)), data)
+        .map_err(internal_error)?;
+    let out = render_template_file(
+        &admin_dir.join("layout.liquid"),
+        json!({ "title": title, "content": content }),
+    )
+    .map_err(internal_error)?;
+    Ok(Html(out))
+}
+
+/// Signed link to the PNG of a rendered screen, valid long enough to keep
+/// an admin page open for a while. Screens only stored as BMP are converted
+/// first; `None` when there is no copy to link to.
+async fn preview_url(state: &AppState, image: &str) -> Option<String> {
+    let image = RenderedImage::from_id(image);
+    if let Err(err) = image
+        .ensure_format(state.storage.as_ref(), ScreenFormat::Png)
+        .await
+    {
+        warn!("no preview for screen {}: {:#}", image.id(), err);
+        return None;
+    }
+    let key = image.png_key();
+    let query = state.signer.sign(&key, Duration::from_secs(60 * 60));
+    Some(format!("/storage/images/generated/{}?{}", key, query))
+}
+
+fn plugin_fields(state: &AppState, plugin: &Value) -> Result<String, StatusCode> {
+    render_template_file(
+        &state
+            .config
+            .paths
+            .templates_dir
+            .join("admin/plugin_fields.liquid"),
+        json!({
+            "plugin": plugin,
+            "layouts": layout_names(&state.config.paths.templates_dir),
+        }),
+    )
+    .map_err(internal_error)
+}
+
+pub async fn devices_page(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let devices = Device::all(&state.db).await.map_err(internal_error)?;
+    let mut rendered = Vec::with_capacity(devices.len());
+    for device in devices {
+        let preview_url = match &device.current_screen_image {
+            Some(image) => preview_url(&state, image).await,
+            None => None,
+        };
+        let mut value = json!(DeviceResponse::from(device));
+        value["preview_url"] = json!(preview_url);
+        rendered.push(value);
+    }
+    page(&state, "Devices", "devices", json!({ "devices": rendered }))
+}
+
+pub async fn plugins_page(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let plugins = Plugin::all(&state.db).await.map_err(internal_error)?;
+    let fields = plugin_fields(&state, &json!(PluginParams::default()))?;
+    page(
+        &state,
+        "Plugins",
+        "plugins",
+        json!({ "plugins": plugins, "fields": fields }),
+    )
+}
+
+pub async fn plugin_page(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Html<String>, StatusCode> {
+    let plugin = Plugin::find(&state.db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let plugin = json!(plugin);
+    let fields = plugin_fields(&state, &plugin)?;
+    page(
+        &state,
+        "Plugin",
+        "plugin",
+        json!({ "plugin": plugin, "fields": fields }),
+    )
+}
+
+pub async fn create_plugin(
+    State(state): State<AppState>,
+    Form(form): Form<PluginForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = PluginParams::try_from(form)?;
+    Plugin::create(&state.db, &params)
+        .await
+        .map_err(internal_error)?;
+    Ok(Redirect::to("/admin/plugins"))
+}
+
+pub async fn update_plugin(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(form): Form<PluginForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = PluginParams::try_from(form)?;
+    Plugin::update(&state.db, id, &params)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state.templates.invalidate_plugin(id);
+    Mashup::clear_images_showing(&state.db, id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Redirect::to("/admin/plugins"))
+}
+
+pub async fn delete_plugin(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    Plugin::delete(&state.db, id)
+        .await
+        .map_err(internal_error)?;
+    state.templates.invalidate_plugin(id);
+    Ok(Redirect::to("/admin/plugins"))
+}
+
+pub async fn partials_page(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let partials = Partial::all(&state.db).await.map_err(internal_error)?;
+    page(
+        &state,
+        "Partials",
+        "partials",
+        json!({ "partials": partials }),
+    )
+}
+
+pub async fn partial_page(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Html<String>, StatusCode> {
+    let partial = Partial::find(&state.db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    page(&state, "Partial", "partial", json!({ "partial": partial }))
+}
+
+pub async fn create_partial(
+    State(state): State<AppState>,
+    Form(form): Form<PartialForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = PartialParams::try_from(form)?;
+    // Names are unique
+    Partial::create(&state.db, &params)
+        .await
+        .map_err(unprocessable)?;
+    state.templates.clear();
+    Ok(Redirect::to("/admin/partials"))
+}
+
+pub async fn update_partial(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(form): Form<PartialForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = PartialParams::try_from(form)?;
+    Partial::update(&state.db, id, &params)
+        .await
+        .map_err(unprocessable)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    state.templates.clear();
+    Ok(Redirect::to("/admin/partials"))
+}
+
+pub async fn delete_partial(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    Partial::delete(&state.db, id)
+        .await
+        .map_err(internal_error)?;
+    state.templates.clear();
+    Ok(Redirect::to("/admin/partials"))
+}
+
+pub async fn mashups_page(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let mashups = Mashup::all(&state.db).await.map_err(internal_error)?;
+    let plugins = Plugin::all(&state.db).await.map_err(internal_error)?;
+
+    let mut rendered = Vec::with_capacity(mashups.len());
+    for mashup in mashups {
+        let names: Vec<String> = mashup
+            .plugins(&state.db)
+            .await
+            .map_err(internal_error)?
+            .into_iter()
+            .map(|plugin| plugin.name)
+            .collect();
+        let preview_url = match &mashup.current_image {
+            Some(image) => preview_url(&state, image).await,
+            None => None,
+        };
+        let mut value = json!(mashup);
+        value["plugin_names"] = json!(names);
+        value["preview_url"] = json!(preview_url);
+        rendered.push(value);
+    }
+
+    let layouts: Vec<Value> = MashupLayout::ALL
+        .iter()
+        .map(|layout| json!({ "name": layout.name(), "slots": layout.views().len() }))
+        .collect();
+    page(
+        &state,
+        "Mashups",
+        "mashups",
+        json!({ "mashups": rendered, "plugins": plugins, "layouts": layouts }),
+    )
+}
+
+pub async fn create_mashup(
+    State(state): State<AppState>,
+    Form(form): Form<MashupForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = MashupParams::try_from(form)?;
+    // Fails on unknown plugin ids
+    Mashup::create(&state.db, &params)
+        .await
+        .map_err(unprocessable)?;
+    Ok(Redirect::to("/admin/mashups"))
+}
+
+pub async fn delete_mashup(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    Mashup::delete(&state.db, id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Redirect::to("/admin/mashups"))
+}
+
+pub async fn playlists_page(State(state): State<AppState>) -> Result<Html<String>, StatusCode> {
+    let devices = Device::all(&state.db).await.map_err(internal_error)?;
+    let plugins = Plugin::all(&state.db).await.map_err(internal_error)?;
+    let mashups = Mashup::all(&state.db).await.map_err(internal_error)?;
+    let playlists = Playlist::all(&state.db).await.map_err(internal_error)?;
+
+    let mut rendered = Vec::with_capacity(playlists.len());
+    for playlist in playlists {
+        let items = playlist.items(&state.db).await.map_err(internal_error)?;
+        let items: Vec<Value> = items
+            .into_iter()
+            .map(|item| {
+                let name = match (item.plugin_id, item.mashup_id) {
+                    (Some(id), _) => plugins.iter().find(|p| p.id == id).map(|p| p.name.clone()),
+                    (None, Some(id)) => mashups
+                        .iter()
+                        .find(|m| m.id == id)
+                        .map(|m| format!("{} (mashup)", m.name)),
+                    (None, None) => None,
+                };
+                let mut value = json!(item);
+                value["name"] = json!(name);
+                value
+            })
+            .collect();
+        let device_name = devices
+            .iter()
+            .find(|d| d.id == playlist.device_id)
+            .and_then(|d| d.name.clone().or(d.friendly_id.clone()));
+        let mut value = json!(playlist);
+        value["items"] = json!(items);
+        value["device_name"] = json!(device_name);
+        rendered.push(value);
+    }
+
+    let devices: Vec<DeviceResponse> = devices.into_iter().map(DeviceResponse::from).collect();
+    page(
+        &state,
+        "Playlists",
+        "playlists",
+        json!({
+            "playlists": rendered,
+            "devices": devices,
+            "plugins": plugins,
+            "mashups": mashups,
+        }),
+    )
+}
+
+pub async fn create_playlist(
+    State(state): State<AppState>,
+    Form(form): Form<PlaylistForm>,
+) -> Result<Redirect, StatusCode> {
+    let params = PlaylistParams::try_from(form)?;
+    Playlist::create(&state.db, &params)
+        .await
+        .map_err(unprocessable)?;
+    Ok(Redirect::to("/admin/playlists"))
+}
+
+pub async fn delete_playlist(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    Playlist::delete(&state.db, id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Redirect::to("/admin/playlists"))
+}
+
+pub async fn add_playlist_item(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Form(form): Form<PlaylistItemForm>,
+) -> Result<Redirect, StatusCode> {
+    let playlist = Playlist::find(&state.db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let added = match (form.plugin_id, form.mashup_id) {
+        (Some(plugin_id), None) => playlist.add_item(&state.db, plugin_id).await,
+        (None, Some(mashup_id)) => playlist.add_mashup_item(&state.db, mashup_id).await,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    added.map_err(unprocessable)?;
+    Ok(Redirect::to("/admin/playlists"))
+}
+
+pub async fn remove_playlist_item(
+    Path((id, item_id)): Path<(i64, i64)>,
+    State(state): State<AppState>,
+) -> Result<Redirect, StatusCode> {
+    let playlist = Playlist::find(&state.db, id)
+        .await
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    playlist
+        .remove_item(&state.db, item_id)
+        .await
+        .map_err(internal_error)?;
+    Ok(Redirect::to("/admin/playlists"))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(devices_page))
+        .route("/plugins", get(plugins_page).post(create_plugin))
+        .route("/plugins/{id}", get(plugin_page).post(update_plugin))
+        .route("/plugins/{id}/delete", post(delete_plugin))
+        .route("/mashups", get(mashups_page).post(create_mashup))
+        .route("/mashups/{id}/delete", post(delete_mashup))
+        .route("/partials", get(partials_page).post(create_partial))
+        .route("/partials/{id}", get(partial_page).post(update_partial))
+        .route("/partials/{id}/delete", post(delete_partial))
+        .route("/playlists", get(playlists_page).post(create_playlist))
+        .route("/playlists/{id}/delete", post(delete_playlist))
+        .route("/playlists/{id}/items", post(add_playlist_item))
+        .route(
+            "/playlists/{id}/items/{item_id}/delete",
+            post(remove_playlist_item),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RenderConfig;
+    use crate::{db, models::device::MacAddress};
+
+    use axum::{
+        body::{Body, to_bytes},
+        http::Request,
+    };
+    use tower::ServiceExt;
+
+    async fn test_app() -> (Router, sqlx::SqlitePool) {
+        let (app, pool, _) = test_app_with_state().await;
+        (app, pool)
+    }
+
+    async fn test_app_with_state() -> (Router, sqlx::SqlitePool, AppState) {
+        let pool = db::test_pool().await;
+        let state = AppState::for_tests(pool.clone());
+        (router().with_state(state.clone()), pool, state)
+    }
+
+    async fn get_page(app: Router, uri: &str) -> String {
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn post_form(app: Router, uri: &str, body: &str) -> StatusCode {
+        app.oneshot(
+            Request::post(uri)
+                .header("content-type", "application/x-www-form-urlencoded")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn test_devices_page_shows_telemetry_and_preview() {
+        let (app, pool, state) = test_app_with_state().await;
+        state.storage.put("abc.png", b"png".to_vec()).await.unwrap();
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "secret",
+            "device-EE:FF",
+            "Kitchen <3",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        sqlx::query("UPDATE devices SET current_screen_image = 'abc', last_battery_voltage = 3.9, last_rssi_level = -55 WHERE id = ?")
+            .bind(device.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let html = get_page(app, "/").await;
+        assert!(html.contains("Kitchen &lt;3"));
+        assert!(html.contains("3.9 V"));
+        assert!(html.contains("-55 dBm"));
+        assert!(html.contains("/storage/images/generated/abc.png?expires="));
+        assert!(!html.contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_preview_converts_bmp_screens() {
+        let (app, pool, state) = test_app_with_state().await;
+        let mut bmp = std::io::Cursor::new(Vec::new());
+        image::DynamicImage::new_luma8(2, 2)
+            .write_to(&mut bmp, image::ImageFormat::Bmp)
+            .unwrap();
+        state
+            .storage
+            .put("proxied.bmp", bmp.into_inner())
+            .await
+            .unwrap();
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        for (mac, image) in [
+            (mac, "proxied"),
+            ("AA:BB:CC:DD:EE:00".parse().unwrap(), "gone"),
+        ] {
+            let device = Device::create(
+                &pool,
+                &mac,
+                "secret",
+                "device",
+                "Kitchen",
+                &RenderConfig::default(),
+            )
+            .await
+            .unwrap();
+            Device::set_current_screen_image(&pool, device.id, image)
+                .await
+                .unwrap();
+        }
+
+        let html = get_page(app, "/").await;
+        assert!(html.contains("/storage/images/generated/proxied.png?expires="));
+        assert!(state.storage.exists("proxied.png").await.unwrap());
+        // Nothing stored to link to
+        assert!(!html.contains("gone.png"));
+    }
+
+    #[tokio::test]
+    async fn test_create_and_edit_plugin() {
+        let (app, pool) = test_app().await;
+
+        let status = post_form(
+            app.clone(),
+            "/plugins",
+            "name=Weather&data_strategy=polling&polling_url=http%3A%2F%2Fexample.com&data_stale_minutes=&data_payload=%7B%22t%22%3A1%7D",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let plugin = Plugin::all(&pool).await.unwrap().pop().unwrap();
+        assert_eq!(plugin.polling_url.as_deref(), Some("http://example.com"));
+        assert_eq!(plugin.polling_verb.as_deref(), Some("GET"));
+        assert_eq!(plugin.data_stale_minutes, None);
+
+        let html = get_page(app.clone(), &format!("/plugins/{}", plugin.id)).await;
+        assert!(html.contains("value=\"Weather\""));
+
+        let status = post_form(
+            app.clone(),
+            &format!("/plugins/{}", plugin.id),
+            "name=Forecast&data_stale_minutes=15",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let plugin = Plugin::find(&pool, plugin.id).await.unwrap().unwrap();
+        assert_eq!(plugin.name, "Forecast");
+        assert_eq!(plugin.data_stale_minutes, Some(15));
+
+        let html = get_page(app, "/plugins").await;
+        assert!(html.contains("Forecast"));
+    }
+
+    #[tokio::test]
+    async fn test_edit_plugin_drops_stale_image() {
+        let (app, pool) = test_app().await;
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Weather".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        Plugin::set_current_image(&pool, plugin.id, "old")
+            .await
+            .unwrap();
+
+        let status = post_form(
+            app,
+            &format!("/plugins/{}", plugin.id),
+            "name=Weather&render_markup=%3Cp%3Enew%3C%2Fp%3E",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let plugin = Plugin::find(&pool, plugin.id).await.unwrap().unwrap();
+        assert_eq!(plugin.current_image, None);
+    }
+
+    #[tokio::test]
+    async fn test_create_plugin_rejects_invalid_payload() {
+        let (app, _) = test_app().await;
+
+        let status = post_form(app, "/plugins", "name=Broken&data_payload=%7Bnope").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_layout_field() {
+        let (app, pool) = test_app().await;
+
+        let html = get_page(app.clone(), "/plugins").await;
+        assert!(html.contains(r#"<option value="base""#));
+
+        let status = post_form(app.clone(), "/plugins", "name=Clock&layout=base").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let plugin = Plugin::all(&pool).await.unwrap().pop().unwrap();
+        assert_eq!(plugin.layout, None);
+
+        let status = post_form(
+            app,
+            &format!("/plugins/{}", plugin.id),
+            "name=Clock&layout=framed",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let plugin = Plugin::find(&pool, plugin.id).await.unwrap().unwrap();
+        assert_eq!(plugin.layout.as_deref(), Some("framed"));
+    }
+
+    #[tokio::test]
+    async fn test_mashup_workflow() {
+        let (app, pool) = test_app().await;
+        let mut ids = Vec::new();
+        for name in ["Weather", "Calendar"] {
+            let plugin = Plugin::create(
+                &pool,
+                &PluginParams {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(plugin.id);
+        }
+
+        // 2x2 needs four plugins
+        let status = post_form(
+            app.clone(),
+            "/mashups",
+            &format!(
+                "name=Desk&layout=2x2&plugin_1={}&plugin_2={}&plugin_3=&plugin_4=",
+                ids[0], ids[1]
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let status = post_form(
+            app.clone(),
+            "/mashups",
+            &format!(
+                "name=Desk&layout=1Lx1R&plugin_1={}&plugin_2={}&plugin_3=&plugin_4=",
+                ids[0], ids[1]
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let mashup = Mashup::all(&pool).await.unwrap().pop().unwrap();
+        assert_eq!(mashup.layout, "1Lx1R");
+
+        let html = get_page(app.clone(), "/mashups").await;
+        assert!(html.contains("Desk"));
+        assert!(html.contains("Weather, Calendar"));
+
+        let status = post_form(app, &format!("/mashups/{}/delete", mashup.id), "").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert!(Mashup::all(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_partial_workflow() {
+        let (app, pool) = test_app().await;
+
+        let status = post_form(
+            app.clone(),
+            "/partials",
+            "name=title_bar&markup=%3Ch1%3E%7B%7B+title+%7D%7D%3C%2Fh1%3E",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let partial = Partial::all(&pool).await.unwrap().pop().unwrap();
+        assert_eq!(partial.markup, "<h1>{{ title }}</h1>");
+
+        let html = get_page(app.clone(), "/partials").await;
+        assert!(html.contains("{% render &quot;title_bar&quot; %}"));
+        let html = get_page(app.clone(), &format!("/partials/{}", partial.id)).await;
+        assert!(html.contains("&lt;h1&gt;{{ title }}&lt;/h1&gt;"));
+
+        let status = post_form(app.clone(), "/partials", "name=title_bar&markup=x").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let status = post_form(app.clone(), "/partials", "name=bad+name&markup=x").await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+        let status = post_form(app, &format!("/partials/{}/delete", partial.id), "").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert!(Partial::all(&pool).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_playlist_workflow() {
+        let (app, pool) = test_app().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "secret",
+            "device-EE:FF",
+            "Hallway",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Clock".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let status = post_form(
+            app.clone(),
+            "/playlists",
+            &format!(
+                "device_id={}&name=Mornings&weekdays=mon&refresh_time=",
+                device.id
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let playlist = Playlist::all(&pool).await.unwrap().pop().unwrap();
+
+        let status = post_form(
+            app.clone(),
+            &format!("/playlists/{}/items", playlist.id),
+            &format!("plugin_id={}", plugin.id),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+
+        let mashup = Mashup::create(
+            &pool,
+            &MashupParams {
+                name: "Dashboard".to_string(),
+                layout: MashupLayout::OneLeftOneRight,
+                plugin_ids: vec![plugin.id, plugin.id],
+            },
+        )
+        .await
+        .unwrap();
+        let status = post_form(
+            app.clone(),
+            &format!("/playlists/{}/items", playlist.id),
+            &format!("mashup_id={}", mashup.id),
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let status = post_form(
+            app.clone(),
+            &format!("/playlists/{}/items", playlist.id),
+            &format!("plugin_id={}&mashup_id={}", plugin.id, mashup.id),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let html = get_page(app.clone(), "/playlists").await;
+        assert!(html.contains("Mornings · Hallway"));
+        assert!(html.contains("Clock"));
+        assert!(html.contains("Dashboard (mashup)"));
+
+        let item = playlist.items(&pool).await.unwrap().pop().unwrap();
+        assert_eq!(item.mashup_id, Some(mashup.id));
+        let status = post_form(
+            app,
+            &format!("/playlists/{}/items/{}/delete", playlist.id, item.id),
+            "",
+        )
+        .await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        assert_eq!(playlist.items(&pool).await.unwrap().len(), 1);
+    }
+}
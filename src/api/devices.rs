@@ -0,0 +1,467 @@
+use axum::{
+    Json, Router,
+    extract::{Path, Query, State, rejection::JsonRejection},
+    http::{StatusCode, header},
+    response::IntoResponse,
+    routing::get,
+};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::error::ApiError,
+    models::{
+        device::{Device, DeviceUpdate},
+        screen::ScreenRecord,
+        state::AppState,
+    },
+    render::image::{RenderedImage, ScreenFormat, convert},
+};
+
+/// Most history entries returned at once
+const MAX_HISTORY: i64 = 1000;
+
+/// A device as exposed by the management API, without its access token
+#[derive(Serialize, Debug)]
+pub struct DeviceResponse {
+    pub id: i64,
+    pub name: Option<String>,
+    pub mac_address: String,
+    pub friendly_id: Option<String>,
+    pub proxy_cloud: bool,
+    pub current_screen_image: Option<String>,
+    pub last_battery_voltage: Option<f64>,
+    pub last_rssi_level: Option<i32>,
+    pub last_firmware_version: Option<String>,
+    pub default_refresh_interval: i32,
+    pub width: i32,
+    pub height: i32,
+    pub rotate: i32,
+    pub image_format: String,
+    pub model: Option<String>,
+    pub last_refresh_rate: Option<i32>,
+    pub last_seen_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+impl From<Device> for DeviceResponse {
+    fn from(device: Device) -> Self {
+        DeviceResponse {
+            id: device.id,
+            name: device.name,
+            mac_address: device.mac_address,
+            friendly_id: device.friendly_id,
+            proxy_cloud: device.proxy_cloud,
+            current_screen_image: device.current_screen_image,
+            last_battery_voltage: device.last_battery_voltage,
+            last_rssi_level: device.last_rssi_level,
+            last_firmware_version: device.last_firmware_version,
+            default_refresh_interval: device.default_refresh_interval,
+            width: device.width,
+            height: device.height,
+            rotate: device.rotate,
+            image_format: device.image_format,
+            model: device.model,
+            last_refresh_rate: device.last_refresh_rate,
+            last_seen_at: device.last_seen_at,
+            created_at: device.created_at,
+            updated_at: device.updated_at,
+        }
+    }
+}
+
+pub async fn list_devices(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<DeviceResponse>>, ApiError> {
+    let devices = Device::all(&state.db).await?;
+    Ok(Json(
+        devices.into_iter().map(DeviceResponse::from).collect(),
+    ))
+}
+
+pub async fn get_device(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<Json<DeviceResponse>, ApiError> {
+    let device = Device::find(&state.db, id)
+        .await?
+        .ok_or_else(|| missing_device(id))?;
+    Ok(Json(device.into()))
+}
+
+pub async fn update_device(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    update: Result<Json<DeviceUpdate>, JsonRejection>,
+) -> Result<Json<DeviceResponse>, ApiError> {
+    let Json(update) = update?;
+    update.validate().map_err(ApiError::invalid)?;
+    let device = Device::update(&state.db, id, &update)
+        .await?
+        .ok_or_else(|| missing_device(id))?;
+    Ok(Json(device.into()))
+}
+
+pub async fn delete_device(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<StatusCode, ApiError> {
+    if Device::delete(&state.db, id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(missing_device(id))
+    }
+}
+
+/// The PNG of the screen the device receives on its next check-in: its
+/// current image, or the setup image until one is assigned. Proxied devices
+/// get their screen from the cloud, see their history for those.
+pub async fn device_screen(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, ApiError> {
+    let device = Device::find(&state.db, id)
+        .await?
+        .ok_or_else(|| missing_device(id))?;
+    let png = match &device.current_screen_image {
+        Some(image) => {
+            let image = RenderedImage::from_id(image);
+            let mut stored = false;
+            for format in ScreenFormat::ALL {
+                stored |= state.storage.exists(&image.key(format)).await?;
+            }
+            if !stored {
+                return Err(ApiError::not_found(format!(
+                    "screen {} is no longer stored",
+                    image.id()
+                )));
+            }
+            image
+                .ensure_format(state.storage.as_ref(), ScreenFormat::Png)
+                .await?;
+            state
+                .storage
+                .get(&image.png_key())
+                .await?
+                .ok_or_else(|| ApiError::not_found(format!("screen {} is gone", image.id())))?
+        }
+        None => {
+            let setup_logo = state.config.paths.assets_dir.join("setup-logo.bmp");
+            let bmp = tokio::fs::read(&setup_logo)
+                .await
+                .map_err(anyhow::Error::from)?;
+            convert(&bmp, ScreenFormat::Png)?
+        }
+    };
+    Ok(([(header::CONTENT_TYPE, "image/png")], png))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct HistoryQuery {
+    /// Number of entries, newest first
+    pub limit: Option<i64>,
+    /// Only screens first shown at or before this time (UTC), so the first
+    /// entry is what was on display then
+    pub before: Option<NaiveDateTime>,
+}
+
+pub async fn screen_history(
+    Path(id): Path<i64>,
+    State(state): State<AppState>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<Vec<ScreenRecord>>, ApiError> {
+    Device::find(&state.db, id)
+        .await?
+        .ok_or_else(|| missing_device(id))?;
+    let limit = query.limit.unwrap_or(50);
+    if !(1..=MAX_HISTORY).contains(&limit) {
+        return Err(ApiError::invalid(format!(
+            "limit must be between 1 and {}",
+            MAX_HISTORY
+        )));
+    }
+    let history = ScreenRecord::for_device(&state.db, id, query.before, limit).await?;
+    Ok(Json(history))
+}
+
+fn missing_device(id: i64) -> ApiError {
+    ApiError::not_found(format!("device {} does not exist", id))
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list_devices))
+        .route(
+            "/{id}",
+            get(get_device).patch(update_device).delete(delete_device),
+        )
+        .route("/{id}/screen", get(device_screen))
+        .route("/{id}/screens", get(screen_history))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RenderConfig;
+    use crate::{db, models::device::MacAddress};
+    use axum::{
+        body::{Body, to_bytes},
+        http::Request,
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn test_app() -> (Router, sqlx::SqlitePool, i64) {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "secret",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let state = AppState::for_tests(pool.clone());
+        (router().with_state(state), pool, device.id)
+    }
+
+    async fn json_body(response: axum::response::Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_list_devices_hides_api_key() {
+        let (app, _, _) = test_app().await;
+
+        let response = app
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        let devices = body.as_array().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0]["mac_address"], "AA:BB:CC:DD:EE:FF");
+        assert!(devices[0].get("api_key").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_missing_device() {
+        let (app, _, _) = test_app().await;
+
+        let response = app
+            .oneshot(Request::get("/999").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["message"], "device 999 does not exist");
+    }
+
+    #[tokio::test]
+    async fn test_patch_device() {
+        let (app, pool, id) = test_app().await;
+
+        let response = app
+            .oneshot(
+                Request::patch(format!("/{}", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"name": "Kitchen", "rotate": 90, "image_format": "bmp"}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["name"], "Kitchen");
+        assert_eq!(body["rotate"], 90);
+
+        let device = Device::find(&pool, id).await.unwrap().unwrap();
+        assert_eq!(device.image_format, "bmp");
+        // Untouched fields keep their values
+        assert_eq!(device.default_refresh_interval, 60);
+        assert_eq!(device.api_key, "secret");
+    }
+
+    #[tokio::test]
+    async fn test_patch_device_null_clears_value() {
+        let (app, pool, id) = test_app().await;
+        let update = DeviceUpdate {
+            name: Some(Some("Kitchen".to_string())),
+            proxy_cloud: Some(true),
+            cloud_api_key: Some(Some("cloud-key".to_string())),
+            ..Default::default()
+        };
+        Device::update(&pool, id, &update).await.unwrap();
+
+        let response = app
+            .oneshot(
+                Request::patch(format!("/{}", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(
+                        r#"{"proxy_cloud": false, "cloud_api_key": null}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let device = Device::find(&pool, id).await.unwrap().unwrap();
+        assert_eq!(device.cloud_api_key, None);
+        assert!(!device.proxy_cloud);
+        // Left out, so kept
+        assert_eq!(device.name.as_deref(), Some("Kitchen"));
+    }
+
+    #[tokio::test]
+    async fn test_patch_device_rejects_invalid_rotation() {
+        let (app, _, id) = test_app().await;
+
+        let response = app
+            .oneshot(
+                Request::patch(format!("/{}", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"rotate": 45}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "invalid");
+        assert_eq!(body["message"], "rotate must be one of 0, 90, 180 or 270");
+    }
+
+    #[tokio::test]
+    async fn test_patch_device_rejects_malformed_json() {
+        let (app, _, id) = test_app().await;
+
+        let response = app
+            .oneshot(
+                Request::patch(format!("/{}", id))
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"rotate": "#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["code"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn test_device_screen_is_png() {
+        let (app, pool, id) = test_app().await;
+
+        // Nothing assigned yet, so the setup image
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/{}/screen", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        let png = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+
+        // A screen that is gone from storage
+        sqlx::query("UPDATE devices SET current_screen_image = 'abc' WHERE id = ?")
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let response = app
+            .oneshot(
+                Request::get(format!("/{}/screen", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_screen_history() {
+        let (app, pool, id) = test_app().await;
+        ScreenRecord::record(&pool, id, "setup-logo.bmp")
+            .await
+            .unwrap();
+        ScreenRecord::record(&pool, id, "abc.png").await.unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/{}/screens?limit=1", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["filename"], "abc.png");
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/{}/screens?before=2000-01-01T00:00:00", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(json_body(response).await, serde_json::json!([]));
+
+        let response = app
+            .oneshot(
+                Request::get(format!("/{}/screens?limit=0", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn test_delete_device() {
+        let (app, pool, id) = test_app().await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::delete(format!("/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(Device::find(&pool, id).await.unwrap().is_none());
+
+        let response = app
+            .oneshot(
+                Request::delete(format!("/{}", id))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
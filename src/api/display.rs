@@ -1,6 +1,16 @@
-use crate::models::device::Device;
+use std::time::Duration;
+
+use crate::{
+    models::device::Device,
+    render::image::{RenderedImage, ScreenFormat},
+    storage::{ImageStorage, signing::UrlSigner},
+};
 use serde::Serialize;
 
+/// Seconds a device gets to download its image, which is also how long the
+/// signed link to it stays valid
+pub const IMAGE_URL_TIMEOUT: u32 = 15;
+
 #[derive(Serialize)]
 pub struct DisplayResponse {
     pub image_url: String,
@@ -15,37 +25,26 @@ pub struct DisplayResponse {
 
 impl DisplayResponse {
     // TODO: Potentially migrate this to use a RenderedImage instead
-    pub fn from_device(device: &Device, base_url: &str) -> Self {
-        let (filename, image_path) = if let Some(image_uuid) = &device.current_screen_image {
-            let use_bmp = device
-                .last_firmware_version
-                .as_ref()
-                .map(|v| version_compare(v, "1.5.2") < 0)
-                .unwrap_or(true);
-
-            if use_bmp {
-                (
-                    format!("{}.bmp", image_uuid),
-                    format!("images/generated/{}.bmp", image_uuid),
-                )
-            } else {
-                (
-                    format!("{}.png", image_uuid),
-                    format!("images/generated/{}.png", image_uuid),
-                )
-            }
+    pub fn from_device(
+        device: &Device,
+        base_url: &str,
+        storage: &dyn ImageStorage,
+        signer: &UrlSigner,
+    ) -> Self {
+        let (filename, image_url) = if let Some(image_uuid) = &device.current_screen_image {
+            let filename = RenderedImage::from_id(image_uuid).key(screen_format(device));
+            let image_url = generated_image_url(base_url, &filename, storage, signer);
+            (filename, image_url)
         } else {
             (
                 "setup-logo.bmp".to_string(),
-                "images/setup-logo.bmp".to_string(),
+                format!("{}/storage/images/setup-logo.bmp", base_url),
             )
         };
 
-        let image_url = format!("{}/storage/{}", base_url, image_path);
-
         DisplayResponse {
             image_url,
-            image_url_timeout: 15,
+            image_url_timeout: IMAGE_URL_TIMEOUT,
             filename,
             refresh_rate: device.default_refresh_interval as u32,
             reset_firmware: false,
@@ -56,6 +55,42 @@ impl DisplayResponse {
     }
 }
 
+/// The format a device is sent: its `image_format` when that names one,
+/// otherwise BMP for firmware older than 1.5.2 and PNG for anything newer
+pub fn screen_format(device: &Device) -> ScreenFormat {
+    ScreenFormat::from_name(&device.image_format).unwrap_or_else(|| {
+        let old_firmware = device
+            .last_firmware_version
+            .as_ref()
+            .map(|v| version_compare(v, "1.5.2") < 0)
+            .unwrap_or(true);
+        if old_firmware {
+            ScreenFormat::Bmp
+        } else {
+            ScreenFormat::Png
+        }
+    })
+}
+
+/// Where devices download a stored image: straight from the object store
+/// when it is public, otherwise through this server with a link that expires
+/// after `IMAGE_URL_TIMEOUT`
+pub fn generated_image_url(
+    base_url: &str,
+    filename: &str,
+    storage: &dyn ImageStorage,
+    signer: &UrlSigner,
+) -> String {
+    storage.public_url(filename).unwrap_or_else(|| {
+        format!(
+            "{}/storage/images/generated/{}?{}",
+            base_url,
+            filename,
+            signer.sign(filename, Duration::from_secs(IMAGE_URL_TIMEOUT.into()))
+        )
+    })
+}
+
 // TODO: replace with semver crate?
 fn version_compare(a: &str, b: &str) -> i8 {
     match a.cmp(b) {
@@ -68,8 +103,25 @@ fn version_compare(a: &str, b: &str) -> i8 {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        config::S3Config,
+        storage::{local::LocalStorage, s3::S3Storage},
+    };
     use chrono::NaiveDateTime;
 
+    fn storage() -> LocalStorage {
+        LocalStorage::new(std::env::temp_dir().join("patina-display-tests")).unwrap()
+    }
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new(b"display tests")
+    }
+
+    /// The image URL without its signature
+    fn unsigned(url: &str) -> &str {
+        url.split('?').next().unwrap()
+    }
+
     fn create_test_device() -> Device {
         Device {
             id: 1,
@@ -86,7 +138,11 @@ mod tests {
             width: 800,
             height: 480,
             rotate: 0,
-            image_format: "png".to_string(),
+            image_format: "auto".to_string(),
+            model: None,
+            last_refresh_rate: None,
+            last_seen_at: None,
+            cloud_api_key: None,
             created_at: NaiveDateTime::parse_from_str("2001-09-09 01:46:40", "%Y-%m-%d %H:%M:%S").unwrap(),
             updated_at: NaiveDateTime::parse_from_str("2001-09-09 01:46:40", "%Y-%m-%d %H:%M:%S").unwrap(),
         }
@@ -97,14 +153,14 @@ mod tests {
         let device = create_test_device();
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
         assert_eq!(response.image_url, "https://example.com/storage/images/setup-logo.bmp");
         assert_eq!(response.image_url_timeout, 15);
         assert_eq!(response.filename, "setup-logo.bmp");
         assert_eq!(response.refresh_rate, 60);
-        assert_eq!(response.reset_firmware, false);
-        assert_eq!(response.update_firmware, false);
+        assert!(!response.reset_firmware);
+        assert!(!response.update_firmware);
         assert_eq!(response.firmware_url, None);
         assert_eq!(response.special_function, "sleep");
     }
@@ -116,9 +172,9 @@ mod tests {
         device.last_firmware_version = Some("1.4.0".to_string());
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
-        assert_eq!(response.image_url, "https://example.com/storage/images/generated/test-uuid-123.bmp");
+        assert_eq!(unsigned(&response.image_url), "https://example.com/storage/images/generated/test-uuid-123.bmp");
         assert_eq!(response.filename, "test-uuid-123.bmp");
         assert_eq!(response.refresh_rate, 60);
     }
@@ -130,9 +186,9 @@ mod tests {
         device.last_firmware_version = Some("1.6.0".to_string());
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
-        assert_eq!(response.image_url, "https://example.com/storage/images/generated/test-uuid-456.png");
+        assert_eq!(unsigned(&response.image_url), "https://example.com/storage/images/generated/test-uuid-456.png");
         assert_eq!(response.filename, "test-uuid-456.png");
         assert_eq!(response.refresh_rate, 60);
     }
@@ -144,10 +200,10 @@ mod tests {
         device.last_firmware_version = Some("1.5.2".to_string());
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
         // Version 1.5.2 should use PNG (not less than 1.5.2)
-        assert_eq!(response.image_url, "https://example.com/storage/images/generated/test-uuid-789.png");
+        assert_eq!(unsigned(&response.image_url), "https://example.com/storage/images/generated/test-uuid-789.png");
         assert_eq!(response.filename, "test-uuid-789.png");
     }
 
@@ -158,10 +214,10 @@ mod tests {
         device.last_firmware_version = None;
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
         // No firmware version should default to BMP
-        assert_eq!(response.image_url, "https://example.com/storage/images/generated/test-uuid-no-fw.bmp");
+        assert_eq!(unsigned(&response.image_url), "https://example.com/storage/images/generated/test-uuid-no-fw.bmp");
         assert_eq!(response.filename, "test-uuid-no-fw.bmp");
     }
 
@@ -171,7 +227,7 @@ mod tests {
         device.default_refresh_interval = 120;
         let base_url = "https://example.com";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
         assert_eq!(response.refresh_rate, 120);
     }
@@ -181,11 +237,86 @@ mod tests {
         let device = create_test_device();
         let base_url = "http://localhost:3000";
 
-        let response = DisplayResponse::from_device(&device, base_url);
+        let response = DisplayResponse::from_device(&device, base_url, &storage(), &signer());
 
         assert_eq!(response.image_url, "http://localhost:3000/storage/images/setup-logo.bmp");
     }
 
+    #[test]
+    fn test_display_response_points_at_object_store() {
+        let mut device = create_test_device();
+        device.current_screen_image = Some("abc".to_string());
+        device.last_firmware_version = Some("1.6.0".to_string());
+        let storage = S3Storage::new(&S3Config {
+            endpoint: "http://minio:9000".to_string(),
+            bucket: "screens".to_string(),
+            public_url: Some("https://cdn.example.com".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let response =
+            DisplayResponse::from_device(&device, "https://example.com", &storage, &signer());
+        assert_eq!(
+            response.image_url,
+            "https://cdn.example.com/generated/abc.png"
+        );
+        assert_eq!(response.filename, "abc.png");
+
+        device.current_screen_image = None;
+        let response =
+            DisplayResponse::from_device(&device, "https://example.com", &storage, &signer());
+        assert_eq!(response.image_url, "https://example.com/storage/images/setup-logo.bmp");
+    }
+
+    #[test]
+    fn test_display_response_signs_image_url() {
+        let mut device = create_test_device();
+        device.current_screen_image = Some("abc".to_string());
+        device.last_firmware_version = Some("1.6.0".to_string());
+
+        let response =
+            DisplayResponse::from_device(&device, "https://example.com", &storage(), &signer());
+        let (_, query) = response.image_url.split_once('?').unwrap();
+        let uri = format!("/?{}", query).parse().unwrap();
+        let axum::extract::Query(signature) = axum::extract::Query::try_from_uri(&uri).unwrap();
+        assert!(signer().verify("abc.png", &signature));
+        assert!(!signer().verify("abc.bmp", &signature));
+    }
+
+    #[test]
+    fn test_screen_format_follows_firmware_when_auto() {
+        let mut device = create_test_device();
+        assert_eq!(screen_format(&device), ScreenFormat::Bmp);
+        device.last_firmware_version = Some("1.5.1".to_string());
+        assert_eq!(screen_format(&device), ScreenFormat::Bmp);
+        device.last_firmware_version = Some("1.5.2".to_string());
+        assert_eq!(screen_format(&device), ScreenFormat::Png);
+
+        // Unknown formats are treated like auto
+        device.image_format = "jpeg".to_string();
+        assert_eq!(screen_format(&device), ScreenFormat::Png);
+    }
+
+    #[test]
+    fn test_image_format_overrides_firmware() {
+        let mut device = create_test_device();
+        device.current_screen_image = Some("abc".to_string());
+        device.last_firmware_version = Some("1.4.0".to_string());
+        device.image_format = "png".to_string();
+        assert_eq!(screen_format(&device), ScreenFormat::Png);
+        let response =
+            DisplayResponse::from_device(&device, "https://example.com", &storage(), &signer());
+        assert_eq!(response.filename, "abc.png");
+
+        device.last_firmware_version = Some("1.6.0".to_string());
+        device.image_format = "bmp".to_string();
+        assert_eq!(screen_format(&device), ScreenFormat::Bmp);
+        let response =
+            DisplayResponse::from_device(&device, "https://example.com", &storage(), &signer());
+        assert_eq!(response.filename, "abc.bmp");
+    }
+
     #[test]
     fn test_version_compare_equal() {
         assert_eq!(version_compare("1.5.2", "1.5.2"), 0);
@@ -220,7 +351,7 @@ mod tests {
     #[test]
     fn test_display_response_serialization() {
         let device = create_test_device();
-        let response = DisplayResponse::from_device(&device, "https://test.com");
+        let response = DisplayResponse::from_device(&device, "https://test.com", &storage(), &signer());
         
         // Test that it can be serialized (this will panic if there are issues)
         let json = serde_json::to_string(&response).expect("Should serialize successfully");
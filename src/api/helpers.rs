@@ -1,12 +1,17 @@
-use axum::http::{HeaderMap, StatusCode};
+use axum::http::HeaderMap;
+
+use crate::{
+    api::error::ApiError,
+    models::device::{DeviceTelemetry, MacAddress},
+};
 
 /// Extract a required string header value
-pub fn extract_header_string(headers: &HeaderMap, name: &str) -> Result<String, StatusCode> {
+pub fn extract_header_string(headers: &HeaderMap, name: &str) -> Result<String, ApiError> {
     headers
         .get(name)
         .and_then(|v| v.to_str().ok())
         .map(|s| s.to_string())
-        .ok_or(StatusCode::BAD_REQUEST)
+        .ok_or_else(|| ApiError::bad_request(format!("missing {} header", name)))
 }
 
 /// Extract an optional string header value
@@ -19,4 +24,26 @@ pub fn extract_header_numeric<T: std::str::FromStr>(headers: &HeaderMap, name: &
     headers
         .get(name)
         .and_then(|v| v.to_str().ok()?.parse().ok())
-}
\ No newline at end of file
+}
+
+/// Extract and normalize the device MAC address from the `id` header
+pub fn extract_mac_address(headers: &HeaderMap) -> Result<MacAddress, ApiError> {
+    let id = extract_header_string(headers, "id")?;
+    id.parse()
+        .map_err(|_| ApiError::bad_request(format!("{:?} is not a MAC address", id)))
+}
+
+/// Collect the telemetry headers sent by the firmware on every check-in
+pub fn extract_telemetry(headers: &HeaderMap) -> DeviceTelemetry {
+    DeviceTelemetry {
+        rssi: extract_header_numeric(headers, "rssi"),
+        // Older firmware used an underscore in this header name
+        battery_voltage: extract_header_numeric(headers, "battery-voltage")
+            .or_else(|| extract_header_numeric(headers, "battery_voltage")),
+        firmware_version: extract_header_string_optional(headers, "fw-version"),
+        refresh_rate: extract_header_numeric(headers, "refresh-rate"),
+        width: extract_header_numeric(headers, "width"),
+        height: extract_header_numeric(headers, "height"),
+        model: extract_header_string_optional(headers, "model"),
+    }
+}
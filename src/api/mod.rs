@@ -1,19 +1,31 @@
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{State, rejection::JsonRejection},
     http::{HeaderMap, StatusCode},
     routing::{get, post},
 };
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::models::state::AppState;
-use crate::{models::device::Device, render::RenderedImage};
+use crate::{
+    metrics::METRICS,
+    models::{
+        device::{Device, MacAddress},
+        screen::ScreenRecord,
+    },
+    render::{image::RenderedImage, playlist::advance_playlist, template::basic_template},
+    storage::ImageStorage,
+};
 
+pub mod error;
 mod helpers;
-use helpers::{extract_header_numeric, extract_header_string, extract_header_string_optional};
+use error::ApiError;
+use helpers::{extract_header_string, extract_mac_address, extract_telemetry};
+pub mod devices;
 mod display;
-use display::DisplayResponse;
+mod proxy;
+use display::{DisplayResponse, screen_format};
 
 #[derive(Serialize)]
 pub struct SetupResponse {
@@ -28,12 +40,7 @@ pub struct LogsRequest {
     log: Log,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct LogsResponse {
-    logs_array: Vec<Log>,
-}
-
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Log {
     pub log_id: u32,
     pub creation_timestamp: i64,
@@ -44,7 +51,7 @@ pub struct Log {
     pub log_sourcefile: String,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct DeviceStatusStamp {
     pub wifi_status: String,
     pub wakeup_reason: String,
@@ -58,7 +65,7 @@ pub struct DeviceStatusStamp {
     pub wifi_rssi_level: i32,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct AdditionalInfo {
     pub retry_attempt: u8,
 }
@@ -75,12 +82,12 @@ pub struct AddImageResponse {
     pub bmp_path: String,
 }
 
-impl From<RenderedImage> for AddImageResponse {
-    fn from(image: RenderedImage) -> Self {
+impl AddImageResponse {
+    pub fn new(image: &RenderedImage, storage: &dyn ImageStorage) -> Self {
         AddImageResponse {
             message: "Image added successfully".to_string(),
-            png_path: image.png_path.display().to_string(),
-            bmp_path: image.bmp_path.display().to_string(),
+            png_path: storage.location(&image.png_key()),
+            bmp_path: storage.location(&image.bmp_key()),
         }
     }
 }
@@ -88,19 +95,26 @@ impl From<RenderedImage> for AddImageResponse {
 pub async fn create_device_endpoint(
     headers: HeaderMap,
     State(state): State<AppState>,
-) -> Result<Json<CreateDeviceResponse>, StatusCode> {
-    let mac_address = extract_header_string(&headers, "id")?;
+) -> Result<Json<CreateDeviceResponse>, ApiError> {
+    let mac_address = extract_mac_address(&headers)?;
     let api_key = extract_header_string(&headers, "access-token")?;
-    let friendly_id = format!("device-{}", &mac_address[12..]);
-    Device::create(
+    let friendly_id = mac_address.friendly_id();
+    let device = Device::create(
         &state.db,
         &mac_address,
         &api_key,
         &friendly_id,
         "TRMNL Device",
+        &state.config.render,
     )
-    .await
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    .await?;
+    // The MAC address is already registered under different credentials
+    if device.api_key != api_key {
+        return Err(ApiError::conflict(format!(
+            "{} is already registered with another access token",
+            mac_address
+        )));
+    }
 
     let response = CreateDeviceResponse {
         message: "Succesfully added device".to_string(),
@@ -111,68 +125,123 @@ pub async fn create_device_endpoint(
 pub async fn log_endpoint(
     headers: HeaderMap,
     State(state): State<AppState>,
-    Json(payload): Json<LogsRequest>,
-) -> StatusCode {
-    let mac_address = extract_header_string(&headers, "id");
-    if mac_address.is_err() {
-        return StatusCode::BAD_REQUEST;
-    }
-    let mac_address = mac_address.unwrap();
-    let api_key = extract_header_string(&headers, "access-token");
-    if api_key.is_err() {
-        return StatusCode::BAD_REQUEST;
-    }
-    let api_key = api_key.unwrap();
+    payload: Result<Json<LogsRequest>, JsonRejection>,
+) -> Result<StatusCode, ApiError> {
+    let mac_address = extract_mac_address(&headers)?;
+    let api_key = extract_header_string(&headers, "access-token")?;
+    let Json(payload) = payload?;
     let device = Device::find_by_credentials(&state.db, &mac_address, &api_key)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .unwrap();
-    if device.is_none() {
-        return StatusCode::NOT_FOUND;
-    }
-    let time = chrono::DateTime::from_timestamp(payload.log.creation_timestamp, 0);
-    if time.is_none() {
-        return StatusCode::INTERNAL_SERVER_ERROR;
-    }
-    let time = time.unwrap().naive_local();
+        .await?
+        .ok_or_else(|| unknown_device(&mac_address))?;
+    let time = chrono::DateTime::from_timestamp(payload.log.creation_timestamp, 0)
+        .ok_or_else(|| {
+            ApiError::bad_request(format!(
+                "creation_timestamp {} is out of range",
+                payload.log.creation_timestamp
+            ))
+        })?
+        .naive_local();
     let log = payload.log;
     info!(
         "{} TIME: {} {} file:{}:{}",
         mac_address, time, log.log_message, log.log_sourcefile, log.log_codeline
     );
-    return StatusCode::NO_CONTENT;
+    if let Some(events) = &state.events {
+        events.log(&device, &log);
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn unknown_device(mac_address: &MacAddress) -> ApiError {
+    ApiError::not_found(format!("no device {} with this access token", mac_address))
+}
+
+/// The device's screen from its playlist, converted first if it isn't stored
+/// in the format the device is sent yet
+async fn local_display(state: &AppState, device: &Device) -> DisplayResponse {
+    let device = advance_playlist(
+        &state.db,
+        &state.config,
+        &state.templates,
+        state.storage.as_ref(),
+        device.clone(),
+    )
+    .await
+    .unwrap_or_else(|err| {
+        warn!(
+            "failed to advance the playlist of {}: {:#}",
+            device.mac_address, err
+        );
+        device.clone()
+    });
+    if let Some(image) = &device.current_screen_image {
+        let format = screen_format(&device);
+        if let Err(err) = RenderedImage::from_id(image)
+            .ensure_format(state.storage.as_ref(), format)
+            .await
+        {
+            warn!(
+                "failed to provide screen {} as {}: {:#}",
+                image,
+                format.extension(),
+                err
+            );
+        }
+    }
+    DisplayResponse::from_device(
+        &device,
+        &state.config.base_url,
+        state.storage.as_ref(),
+        &state.signer,
+    )
 }
 
 pub async fn display_endpoint(
     headers: HeaderMap,
     State(state): State<AppState>,
-) -> Result<Json<DisplayResponse>, StatusCode> {
+) -> Result<Json<DisplayResponse>, ApiError> {
     info!("display request received");
-    let mac_address = extract_header_string(&headers, "id")?;
+    let mac_address = extract_mac_address(&headers)?;
     let api_key = extract_header_string(&headers, "access-token")?;
     info!("mac_address {} api_key {}", mac_address, api_key);
 
     let device = Device::find_by_credentials(&state.db, &mac_address, &api_key)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
-        .unwrap();
-
-    let device = device.ok_or(StatusCode::NOT_FOUND)?;
+        .await?
+        .ok_or_else(|| unknown_device(&mac_address))?;
     info!("Device found!");
-    if let (Some(rssi), Some(bat_volt), Some(fw_version)) = (
-        extract_header_numeric::<i32>(&headers, "rssi"),
-        extract_header_numeric::<f64>(&headers, "battery_voltage"),
-        extract_header_string_optional(&headers, "fw-version"),
-    ) {
-        Device::update_device_info(&state.db, rssi, bat_volt, &fw_version)
-            .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        info!("device info updated!");
-    }
+    let telemetry = extract_telemetry(&headers);
+    let device = Device::update_device_info(&state.db, device.id, &telemetry).await?;
+    info!("device info updated!");
 
     info!("attempting to find image");
-    let resp = DisplayResponse::from_device(&device, &state.base_url);
+    METRICS.record_check_in(&device.mac_address);
+    let resp = if device.proxy_cloud {
+        let upstream = proxy::fetch_display(&state, &device, &telemetry).await;
+        METRICS.record_poll("cloud", upstream.is_ok());
+        match upstream {
+            Ok(resp) => resp,
+            Err(err) => {
+                warn!(
+                    "cloud proxy failed for {}, using local screen: {}",
+                    mac_address, err
+                );
+                local_display(&state, &device).await
+            }
+        }
+    } else {
+        local_display(&state, &device).await
+    };
     info!("displaying {}", resp.image_url);
+    if let Err(err) = ScreenRecord::record(&state.db, device.id, &resp.filename).await {
+        warn!(
+            "failed to record screen history for {}: {}",
+            mac_address, err
+        );
+    }
+    if let Some(events) = &state.events {
+        events.check_in(&device);
+        events.screen(&device, &resp.filename);
+    }
 
     Ok(Json(resp))
 }
@@ -180,39 +249,33 @@ pub async fn display_endpoint(
 pub async fn setup_endpoint(
     headers: HeaderMap,
     State(state): State<AppState>,
-) -> Result<Json<SetupResponse>, StatusCode> {
+) -> Result<Json<SetupResponse>, ApiError> {
     info!("Received setup request!");
-    let mac_address = extract_header_string(&headers, "id")?;
+    let mac_address = extract_mac_address(&headers)?;
 
     info!("Attempting setup for {}", mac_address);
-    let device = Device::find_by_mac(&state.db, &mac_address)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let device = Device::find_by_mac(&state.db, &mac_address).await?;
 
-    let (api_key, friendly_id) = if let Some(device) = device {
-        (
-            device.api_key,
-            device.friendly_id.unwrap_or_else(|| "unknown".to_string()),
-        )
-    } else {
-        let api_key = uuid::Uuid::new_v4().to_string();
-        let friendly_id = format!("device-{}", &mac_address[12..]);
-        let _ = Device::create(
-            &state.db,
-            &mac_address,
-            &api_key,
-            &friendly_id,
-            "TRMNL Device",
-        )
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-        (api_key, friendly_id)
+    let device = match device {
+        Some(device) => device,
+        None => {
+            let api_key = uuid::Uuid::new_v4().to_string();
+            Device::create(
+                &state.db,
+                &mac_address,
+                &api_key,
+                &mac_address.friendly_id(),
+                "TRMNL Device",
+                &state.config.render,
+            )
+            .await?
+        }
     };
 
     let resp = SetupResponse {
-        api_key,
-        friendly_id,
-        image_url: format!("{}/storage/images/setup-logo.bmp", state.base_url),
+        api_key: device.api_key,
+        friendly_id: device.friendly_id.unwrap_or_else(|| "unknown".to_string()),
+        image_url: format!("{}/storage/images/setup-logo.bmp", state.config.base_url),
         message: "Hello from TRMNL!".to_string(),
     };
 
@@ -221,19 +284,17 @@ pub async fn setup_endpoint(
 
 pub async fn render_webpage(
     headers: HeaderMap,
-    State(_): State<AppState>,
-) -> Result<Json<AddImageResponse>, StatusCode> {
+    State(state): State<AppState>,
+) -> Result<Json<AddImageResponse>, ApiError> {
     let url = extract_header_string(&headers, "url")?;
 
     info!("Rendering webpage: {}", url);
-    // This renders a PNG
-    let render_image = RenderedImage::default();
-    let _ = render_image
-        .render(&url)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // The page itself isn't fetched yet, this renders the basic template
+    let html = basic_template(&state.config.paths.templates_dir)?;
+    let render_image =
+        RenderedImage::render_content(&html, &state.config, state.storage.as_ref()).await?;
 
-    let response = AddImageResponse::from(render_image);
+    let response = AddImageResponse::new(&render_image, state.storage.as_ref());
 
     Ok(Json(response))
 }
@@ -246,3 +307,419 @@ pub fn router() -> axum::Router<AppState> {
         .route("/add", post(create_device_endpoint))
         .route("/render", post(render_webpage))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RenderConfig;
+    use crate::db;
+    use crate::models::{
+        device::{DeviceTelemetry, DeviceUpdate, MacAddress},
+        mashup::{Mashup, MashupLayout, MashupParams},
+        playlist::{Playlist, PlaylistParams},
+        plugin::{Plugin, PluginParams},
+    };
+    use axum::{
+        body::{Body, to_bytes},
+        http::Request,
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    async fn test_app() -> (Router, sqlx::SqlitePool) {
+        let pool = db::test_pool().await;
+        let state = AppState::for_tests(pool.clone());
+        (router().with_state(state), pool)
+    }
+
+    async fn json_body(response: axum::response::Response) -> Value {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_setup_then_display_records_telemetry() {
+        let (app, pool) = test_app().await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/setup")
+                    .header("ID", "aa:bb:cc:dd:ee:ff")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let setup = json_body(response).await;
+        assert_eq!(setup["friendly_id"], "device-EE:FF");
+        let api_key = setup["api_key"].as_str().unwrap().to_string();
+
+        let response = app
+            .oneshot(
+                Request::get("/display")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", &api_key)
+                    .header("RSSI", "-61")
+                    .header("Battery-Voltage", "4.1")
+                    .header("FW-Version", "1.5.5")
+                    .header("Refresh-Rate", "900")
+                    .header("Width", "800")
+                    .header("Height", "480")
+                    .header("Model", "og")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let display = json_body(response).await;
+        assert_eq!(display["filename"], "setup-logo.bmp");
+        assert_eq!(display["refresh_rate"], 60);
+
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::find_by_mac(&pool, &mac).await.unwrap().unwrap();
+        assert_eq!(device.api_key, api_key);
+        assert_eq!(device.last_rssi_level, Some(-61));
+        assert_eq!(device.last_battery_voltage, Some(4.1));
+        assert_eq!(device.last_firmware_version.as_deref(), Some("1.5.5"));
+        assert_eq!(device.last_refresh_rate, Some(900));
+        assert_eq!(device.model.as_deref(), Some("og"));
+        assert_eq!((device.width, device.height), (800, 480));
+        assert!(device.last_seen_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_display_records_screen_history() {
+        let (app, pool) = test_app().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get("/display")
+                        .header("ID", "AA:BB:CC:DD:EE:FF")
+                        .header("Access-Token", "key")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        // Repeats of the same screen extend one entry
+        let history = ScreenRecord::for_device(&pool, device.id, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].filename, "setup-logo.bmp");
+    }
+
+    #[tokio::test]
+    async fn test_display_keeps_telemetry_not_sent() {
+        let (app, pool) = test_app().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let telemetry = DeviceTelemetry {
+            rssi: Some(-40),
+            ..Default::default()
+        };
+        Device::update_device_info(&pool, device.id, &telemetry)
+            .await
+            .unwrap();
+
+        let response = app
+            .oneshot(
+                Request::get("/display")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", "key")
+                    .header("Battery-Voltage", "3.9")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let device = Device::find_by_mac(&pool, &mac).await.unwrap().unwrap();
+        assert_eq!(device.last_rssi_level, Some(-40));
+        assert_eq!(device.last_battery_voltage, Some(3.9));
+    }
+
+    #[tokio::test]
+    async fn test_display_falls_back_when_cloud_unreachable() {
+        let (app, pool) = test_app().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let update = DeviceUpdate {
+            proxy_cloud: Some(true),
+            cloud_api_key: Some(Some("cloud-key".to_string())),
+            ..Default::default()
+        };
+        Device::update(&pool, device.id, &update).await.unwrap();
+
+        let response = app
+            .oneshot(
+                Request::get("/display")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", "key")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let display = json_body(response).await;
+        assert_eq!(display["filename"], "setup-logo.bmp");
+    }
+
+    #[tokio::test]
+    async fn test_display_falls_back_to_playlist() {
+        let pool = db::test_pool().await;
+        let state = AppState::for_tests(pool.clone());
+        let app = router().with_state(state.clone());
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let update = DeviceUpdate {
+            proxy_cloud: Some(true),
+            cloud_api_key: Some(Some("cloud-key".to_string())),
+            ..Default::default()
+        };
+        Device::update(&pool, device.id, &update).await.unwrap();
+
+        // Two plugins whose screens are already rendered
+        let mut plugins = Vec::new();
+        for (name, image) in [("Weather", "weather"), ("Calendar", "calendar")] {
+            let plugin = Plugin::create(
+                &pool,
+                &PluginParams {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            Plugin::set_current_image(&pool, plugin.id, image)
+                .await
+                .unwrap();
+            state
+                .storage
+                .put(&format!("{}.bmp", image), b"BM".to_vec())
+                .await
+                .unwrap();
+            plugins.push(plugin);
+        }
+        let playlist = Playlist::create(
+            &pool,
+            &PlaylistParams {
+                device_id: device.id,
+                name: "Kitchen".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        for plugin in &plugins {
+            playlist.add_item(&pool, plugin.id).await.unwrap();
+        }
+
+        for expected in ["weather.bmp", "calendar.bmp", "weather.bmp"] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get("/display")
+                        .header("ID", "AA:BB:CC:DD:EE:FF")
+                        .header("Access-Token", "key")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(json_body(response).await["filename"], expected);
+        }
+        let device = Device::find_by_mac(&pool, &mac).await.unwrap().unwrap();
+        assert_eq!(device.current_screen_image.as_deref(), Some("weather"));
+    }
+
+    #[tokio::test]
+    async fn test_display_shows_playlist_mashup() {
+        let pool = db::test_pool().await;
+        let state = AppState::for_tests(pool.clone());
+        let app = router().with_state(state.clone());
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Weather".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let mashup = Mashup::create(
+            &pool,
+            &MashupParams {
+                name: "Dashboard".to_string(),
+                layout: MashupLayout::OneLeftOneRight,
+                plugin_ids: vec![plugin.id, plugin.id],
+            },
+        )
+        .await
+        .unwrap();
+        Mashup::set_current_image(&pool, mashup.id, "dashboard")
+            .await
+            .unwrap();
+        state
+            .storage
+            .put("dashboard.bmp", b"BM".to_vec())
+            .await
+            .unwrap();
+        let playlist = Playlist::create(
+            &pool,
+            &PlaylistParams {
+                device_id: device.id,
+                name: "Kitchen".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        playlist.add_mashup_item(&pool, mashup.id).await.unwrap();
+
+        let response = app
+            .oneshot(
+                Request::get("/display")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", "key")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["filename"], "dashboard.bmp");
+    }
+
+    #[tokio::test]
+    async fn test_display_rejects_unknown_device() {
+        let (app, _) = test_app().await;
+
+        let response = app
+            .oneshot(
+                Request::get("/display")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", "missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(
+            body["message"],
+            "no device AA:BB:CC:DD:EE:FF with this access token"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_log_rejects_malformed_body() {
+        let (app, pool) = test_app().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        let response = app
+            .oneshot(
+                Request::post("/log")
+                    .header("ID", "AA:BB:CC:DD:EE:FF")
+                    .header("Access-Token", "key")
+                    .header("content-type", "application/json")
+                    .body(Body::from(r#"{"log": {}}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(json_body(response).await["code"], "invalid_body");
+    }
+
+    #[tokio::test]
+    async fn test_setup_rejects_invalid_mac() {
+        let (app, _) = test_app().await;
+
+        let response = app
+            .oneshot(
+                Request::get("/setup")
+                    .header("ID", "short")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "bad_request");
+        assert_eq!(body["message"], r#""short" is not a MAC address"#);
+    }
+}
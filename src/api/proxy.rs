@@ -0,0 +1,280 @@
+use anyhow::{Context, anyhow};
+use log::debug;
+use serde::Deserialize;
+
+use crate::{
+    api::display::{DisplayResponse, IMAGE_URL_TIMEOUT, generated_image_url},
+    models::{
+        device::{Device, DeviceTelemetry},
+        state::AppState,
+    },
+};
+
+/// The subset of the TRMNL cloud `/api/display` response we rely on
+#[derive(Deserialize, Debug)]
+pub struct UpstreamDisplay {
+    pub image_url: String,
+    pub filename: Option<String>,
+    pub refresh_rate: Option<u32>,
+    #[serde(default)]
+    pub reset_firmware: bool,
+    #[serde(default)]
+    pub update_firmware: bool,
+    pub firmware_url: Option<String>,
+    pub special_function: Option<String>,
+}
+
+/// Fetch the current screen for a proxied device from the upstream TRMNL
+/// service and cache the image locally so the device downloads it from us.
+pub async fn fetch_display(
+    state: &AppState,
+    device: &Device,
+    telemetry: &DeviceTelemetry,
+) -> Result<DisplayResponse, anyhow::Error> {
+    let cloud_api_key = device
+        .cloud_api_key
+        .as_deref()
+        .ok_or_else(|| anyhow!("device {} has no cloud API key", device.id))?;
+
+    let mut request = state
+        .http
+        .get(format!("{}/api/display", state.config.cloud_url))
+        .header("ID", &device.mac_address)
+        .header("Access-Token", cloud_api_key);
+    // Forward what the device told us so the cloud dashboard stays accurate
+    let forwarded = [
+        ("RSSI", telemetry.rssi.map(|v| v.to_string())),
+        (
+            "Battery-Voltage",
+            telemetry.battery_voltage.map(|v| v.to_string()),
+        ),
+        ("FW-Version", telemetry.firmware_version.clone()),
+        (
+            "Refresh-Rate",
+            telemetry.refresh_rate.map(|v| v.to_string()),
+        ),
+        ("Width", telemetry.width.map(|v| v.to_string())),
+        ("Height", telemetry.height.map(|v| v.to_string())),
+        ("Model", telemetry.model.clone()),
+    ];
+    for (name, value) in forwarded {
+        if let Some(value) = value {
+            request = request.header(name, value);
+        }
+    }
+
+    let body = request.send().await?.error_for_status()?.bytes().await?;
+    let upstream: UpstreamDisplay =
+        serde_json::from_slice(&body).context("invalid upstream display response")?;
+
+    let filename = cache_image(state, device, &upstream).await?;
+
+    Ok(DisplayResponse {
+        image_url: generated_image_url(
+            &state.config.base_url,
+            &filename,
+            state.storage.as_ref(),
+            &state.signer,
+        ),
+        image_url_timeout: IMAGE_URL_TIMEOUT,
+        filename,
+        refresh_rate: upstream
+            .refresh_rate
+            .unwrap_or(device.default_refresh_interval as u32),
+        reset_firmware: upstream.reset_firmware,
+        update_firmware: upstream.update_firmware,
+        firmware_url: upstream.firmware_url,
+        special_function: upstream
+            .special_function
+            .unwrap_or_else(|| "sleep".to_string()),
+    })
+}
+
+/// Download the upstream image unless it is already cached, returning the
+/// key it is stored under.
+async fn cache_image(
+    state: &AppState,
+    device: &Device,
+    upstream: &UpstreamDisplay,
+) -> Result<String, anyhow::Error> {
+    let upstream_name = upstream
+        .filename
+        .clone()
+        .or_else(|| upstream.image_url.rsplit('/').next().map(str::to_string))
+        .unwrap_or_default();
+    let stem = sanitize(&upstream_name);
+    if stem.is_empty() {
+        return Err(anyhow!("upstream response has no usable filename"));
+    }
+
+    let known_extension = [".bmp", ".png"].iter().any(|ext| stem.ends_with(ext));
+    let candidates = if known_extension {
+        vec![format!("cloud-{}-{}", device.id, stem)]
+    } else {
+        vec![
+            format!("cloud-{}-{}.bmp", device.id, stem),
+            format!("cloud-{}-{}.png", device.id, stem),
+        ]
+    };
+    for filename in candidates {
+        if state.storage.exists(&filename).await? {
+            debug!("using cached upstream image {}", filename);
+            return Ok(filename);
+        }
+    }
+
+    let response = state
+        .http
+        .get(&upstream.image_url)
+        .send()
+        .await?
+        .error_for_status()?;
+    let filename = if known_extension {
+        format!("cloud-{}-{}", device.id, stem)
+    } else {
+        let extension = match response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+        {
+            Some("image/png") => "png",
+            _ => "bmp",
+        };
+        format!("cloud-{}-{}.{}", device.id, stem, extension)
+    };
+    let bytes = response.bytes().await?;
+    state.storage.put(&filename, bytes.to_vec()).await?;
+    debug!("cached upstream image {}", filename);
+    Ok(filename)
+}
+
+/// Keep upstream file names safe to use as a storage key
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{Config, RenderConfig},
+        db,
+        models::device::MacAddress,
+    };
+    use axum::{Json, Router, http::HeaderMap, routing::get};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    /// Serve a minimal stand-in for the TRMNL cloud on a random local port
+    async fn mock_upstream(filename: String) -> String {
+        let app = Router::new()
+            .route(
+                "/api/display",
+                get(|headers: HeaderMap| async move {
+                    let filename = filename.clone();
+                    if headers.get("access-token").and_then(|v| v.to_str().ok())
+                        != Some("cloud-key")
+                    {
+                        return Err(axum::http::StatusCode::UNAUTHORIZED);
+                    }
+                    let host = headers["host"].to_str().unwrap().to_string();
+                    Ok(Json(json!({
+                        "image_url": format!("http://{}/images/screen", host),
+                        "filename": filename,
+                        "refresh_rate": 900,
+                        "special_function": "identify",
+                    })))
+                }),
+            )
+            .route(
+                "/images/screen",
+                get(|| async { ([("content-type", "image/png")], "not really a png") }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn with_upstream(pool: &sqlx::SqlitePool, cloud_url: String) -> AppState {
+        let mut state = AppState::for_tests(pool.clone());
+        let mut config = Config::for_tests();
+        config.cloud_url = cloud_url;
+        state.config = Arc::new(config);
+        state
+    }
+
+    async fn proxied_device(pool: &sqlx::SqlitePool, cloud_api_key: &str) -> Device {
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            pool,
+            &mac,
+            "local-key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        sqlx::query("UPDATE devices SET proxy_cloud = TRUE, cloud_api_key = ? WHERE id = ?")
+            .bind(cloud_api_key)
+            .bind(device.id)
+            .execute(pool)
+            .await
+            .unwrap();
+        Device::find_by_mac(pool, &mac).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_fetch_display_caches_upstream_image() {
+        let pool = db::test_pool().await;
+        // Unique per run so a previously cached file is never picked up
+        let upstream_name = format!("plugin-{}", uuid::Uuid::new_v4());
+        let state = with_upstream(&pool, mock_upstream(upstream_name.clone()).await);
+        let device = proxied_device(&pool, "cloud-key").await;
+
+        let resp = fetch_display(&state, &device, &DeviceTelemetry::default())
+            .await
+            .unwrap();
+
+        let expected = format!("cloud-{}-{}.png", device.id, upstream_name);
+        assert_eq!(resp.filename, expected);
+        assert_eq!(
+            resp.image_url.split('?').next().unwrap(),
+            format!(
+                "http://localhost:3000/storage/images/generated/{}",
+                expected
+            )
+        );
+        assert_eq!(resp.refresh_rate, 900);
+        assert_eq!(resp.special_function, "identify");
+        assert_eq!(
+            state.storage.get(&expected).await.unwrap().unwrap(),
+            b"not really a png"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_display_fails_on_upstream_error() {
+        let pool = db::test_pool().await;
+        let state = with_upstream(&pool, mock_upstream("plugin-1".to_string()).await);
+        let device = proxied_device(&pool, "wrong-key").await;
+
+        assert!(
+            fetch_display(&state, &device, &DeviceTelemetry::default())
+                .await
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("plugin-42.bmp"), "plugin-42.bmp");
+        assert_eq!(sanitize("../../etc/passwd"), "etcpasswd");
+        assert_eq!(sanitize("a b/c?d"), "abcd");
+    }
+}
@@ -0,0 +1,207 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, Method, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use base64::{Engine, prelude::BASE64_STANDARD};
+use log::{debug, warn};
+
+use crate::{api::error::ApiError, config::Config, models::state::AppState};
+
+/// User name expected with HTTP basic auth; API clients can send the password
+/// as a bearer token instead
+pub const ADMIN_USER: &str = "admin";
+
+/// The password guarding the admin dashboard and the device management API
+#[derive(Clone)]
+pub struct AdminAuth {
+    password: Arc<str>,
+}
+
+impl AdminAuth {
+    pub fn new(password: &str) -> Self {
+        AdminAuth {
+            password: password.into(),
+        }
+    }
+
+    /// Auth using `admin_password`, or a random password that is logged once
+    /// and only lasts until the server restarts
+    pub fn from_config(config: &Config) -> Self {
+        match &config.admin_password {
+            Some(password) => AdminAuth::new(password),
+            None => {
+                let password: String = rand::random::<[u8; 12]>()
+                    .iter()
+                    .map(|byte| format!("{:02x}", byte))
+                    .collect();
+                warn!(
+                    "admin_password is not set, log in as {} with {} until the next restart",
+                    ADMIN_USER, password
+                );
+                AdminAuth::new(&password)
+            }
+        }
+    }
+
+    /// Whether the request carries the admin password, either as
+    /// `Authorization: Basic` for `admin` or as `Authorization: Bearer`
+    pub fn verify(&self, headers: &HeaderMap) -> bool {
+        let Some(authorization) = headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let password = match authorization.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => {
+                token.trim().to_string()
+            }
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                let Some(decoded) = BASE64_STANDARD
+                    .decode(credentials.trim())
+                    .ok()
+                    .and_then(|bytes| String::from_utf8(bytes).ok())
+                else {
+                    return false;
+                };
+                match decoded.split_once(':') {
+                    Some((ADMIN_USER, password)) => password.to_string(),
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        };
+        constant_time_eq(password.as_bytes(), self.password.as_bytes())
+    }
+}
+
+/// Compare secrets without leaking how much of them matched through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Whether a browser sent the request from one of this server's own pages.
+/// Requests naming neither an `Origin` nor a `Referer` don't come from a
+/// browser page and pass.
+fn same_origin(headers: &HeaderMap, base_url: &str) -> bool {
+    let source = [header::ORIGIN, header::REFERER]
+        .iter()
+        .find_map(|name| headers.get(name));
+    let Some(source) = source else {
+        return true;
+    };
+    let Some(source) = source
+        .to_str()
+        .ok()
+        .and_then(|source| reqwest::Url::parse(source).ok())
+    else {
+        return false;
+    };
+    let Some(host) = source.host_str() else {
+        return false;
+    };
+    let authority = match source.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    };
+    let same_host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case(&authority));
+    same_host || reqwest::Url::parse(base_url).is_ok_and(|base| base.origin() == source.origin())
+}
+
+/// Reject requests without the admin password, asking browsers to prompt
+/// for it. Browsers resend basic auth credentials on cross-site form posts,
+/// so requests that change anything must also come from our own pages.
+pub async fn require_admin(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.admin.verify(request.headers()) {
+        debug!("refused unauthenticated request for {}", request.uri());
+        let mut response = ApiError::unauthorized("admin credentials are required").into_response();
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"patina\", charset=\"UTF-8\""),
+        );
+        return response;
+    }
+    let safe = [Method::GET, Method::HEAD, Method::OPTIONS].contains(request.method());
+    if !safe && !same_origin(request.headers(), &state.config.base_url) {
+        warn!("refused cross-site {} {}", request.method(), request.uri());
+        return ApiError::forbidden("cross-site requests are not allowed").into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::AUTHORIZATION, authorization.parse().unwrap());
+        headers
+    }
+
+    fn basic(user: &str, password: &str) -> String {
+        format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{}:{}", user, password))
+        )
+    }
+
+    #[test]
+    fn test_verify() {
+        let auth = AdminAuth::new("hunter22hunter22");
+        assert!(auth.verify(&headers(&basic("admin", "hunter22hunter22"))));
+        assert!(auth.verify(&headers("Bearer hunter22hunter22")));
+        assert!(auth.verify(&headers("bearer hunter22hunter22")));
+
+        assert!(!auth.verify(&HeaderMap::new()));
+        assert!(!auth.verify(&headers(&basic("admin", "hunter22"))));
+        assert!(!auth.verify(&headers(&basic("root", "hunter22hunter22"))));
+        assert!(!auth.verify(&headers("Bearer hunter22")));
+        assert!(!auth.verify(&headers("Basic not-base64!")));
+        assert!(!auth.verify(&headers("hunter22hunter22")));
+    }
+
+    #[test]
+    fn test_same_origin() {
+        let base_url = "https://patina.example.com";
+        let mut headers = HeaderMap::new();
+        // curl and other API clients
+        assert!(same_origin(&headers, base_url));
+
+        headers.insert(header::HOST, "192.168.1.10:3000".parse().unwrap());
+        for (name, value, allowed) in [
+            (header::ORIGIN, "http://192.168.1.10:3000", true),
+            (header::ORIGIN, "https://patina.example.com", true),
+            (header::ORIGIN, "https://evil.example.com", false),
+            (header::ORIGIN, "http://192.168.1.10:8080", false),
+            (header::ORIGIN, "null", false),
+            (
+                header::REFERER,
+                "http://192.168.1.10:3000/admin/plugins",
+                true,
+            ),
+            (header::REFERER, "https://evil.example.com/form", false),
+        ] {
+            let mut headers = headers.clone();
+            headers.insert(name.clone(), value.parse().unwrap());
+            assert_eq!(
+                same_origin(&headers, base_url),
+                allowed,
+                "{}: {}",
+                name,
+                value
+            );
+        }
+    }
+}
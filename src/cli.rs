@@ -0,0 +1,404 @@
+use std::{path::PathBuf, time::Duration};
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{Parser, Subcommand};
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    db::{self, export},
+    models::{
+        device::{Device, MacAddress},
+        mashup::Mashup,
+        plugin::Plugin,
+    },
+    render::{
+        cache::TemplateCache, gc, mashup::render_mashup, plugin::render_plugin, preview::Preview,
+    },
+    storage,
+};
+
+#[derive(Parser, Debug)]
+#[command(about = "A TRMNL server", version)]
+pub struct Cli {
+    /// Configuration file, defaults to `PATINA_CONFIG` or `patina.toml`
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run the HTTP server (the default)
+    Serve,
+    /// Apply database migrations and exit
+    Migrate,
+    /// Manage registered devices
+    #[command(subcommand)]
+    Device(DeviceCommand),
+    /// Work with plugins
+    #[command(subcommand)]
+    Plugin(PluginCommand),
+    /// Work with mashups of several plugins
+    #[command(subcommand)]
+    Mashup(MashupCommand),
+    /// Render a Liquid template to an image for local preview
+    #[command(alias = "preview")]
+    RenderTemplate {
+        /// Template markup, embedded in the base layout
+        file: PathBuf,
+        /// JSON data, either inline or a path to a JSON file
+        #[arg(long, default_value = "{}")]
+        data: String,
+        /// Where to write the PNG; a BMP is written alongside
+        #[arg(long)]
+        out: PathBuf,
+        /// A layout from the templates `layouts` directory instead of `base`
+        #[arg(long)]
+        layout: Option<String>,
+        /// Keep running and re-render whenever the template or data changes
+        #[arg(long)]
+        watch: bool,
+    },
+    /// Write every device, plugin, mashup, playlist and partial as JSON,
+    /// without screen history
+    Export {
+        /// Output file, stdout when omitted
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Load a file written by `export`, updating rows with matching ids
+    Import { file: PathBuf },
+    /// Delete generated images that nothing references any more
+    Gc {
+        /// Keep images written within this many hours, defaults to
+        /// `gc.retention_hours`
+        #[arg(long)]
+        retention_hours: Option<u64>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DeviceCommand {
+    /// List registered devices
+    List,
+    /// Register a device
+    Add {
+        mac_address: String,
+        #[arg(long, default_value = "TRMNL Device")]
+        name: String,
+        /// Access token, generated when omitted
+        #[arg(long)]
+        api_key: Option<String>,
+    },
+    /// Delete a device by id or MAC address
+    Remove { device: String },
+    /// Issue a new access token for a device by id or MAC address
+    RotateKey { device: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PluginCommand {
+    /// Render a plugin and make it its current image
+    Render { uuid: String },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum MashupCommand {
+    /// Render a mashup and make it its current image
+    Render { id: i64 },
+}
+
+/// Run any command other than `serve`
+pub async fn run(command: Command, config: &Config) -> Result<()> {
+    if let Command::RenderTemplate {
+        file,
+        data,
+        out,
+        layout,
+        watch,
+    } = command
+    {
+        let preview = Preview {
+            template: file,
+            data,
+            out,
+            layout,
+        };
+        if watch {
+            return preview.watch(config).await;
+        }
+        preview.render(config).await?;
+        println!("{}", preview.out.display());
+        println!("{}", preview.bmp_path().display());
+        return Ok(());
+    }
+
+    let pool = db::initialize(&config.database_url).await?;
+    let templates = TemplateCache::new(&config.paths.templates_dir, config.dev_mode);
+    let storage = storage::from_config(config)?;
+    match command {
+        Command::Serve | Command::RenderTemplate { .. } => unreachable!(),
+        Command::Migrate => println!("Migrations applied to {}", config.database_url),
+        Command::Device(command) => device(&pool, config, command).await?,
+        Command::Plugin(PluginCommand::Render { uuid }) => {
+            let plugin = Plugin::find_by_uuid(&pool, &uuid)
+                .await?
+                .ok_or_else(|| anyhow!("no plugin with uuid {}", uuid))?;
+            let image = render_plugin(&pool, config, &templates, storage.as_ref(), &plugin).await?;
+            println!("{}", storage.location(&image.png_key()));
+            println!("{}", storage.location(&image.bmp_key()));
+        }
+        Command::Mashup(MashupCommand::Render { id }) => {
+            let mashup = Mashup::find(&pool, id)
+                .await?
+                .ok_or_else(|| anyhow!("no mashup {}", id))?;
+            let image = render_mashup(&pool, config, &templates, storage.as_ref(), &mashup).await?;
+            println!("{}", storage.location(&image.png_key()));
+            println!("{}", storage.location(&image.bmp_key()));
+        }
+        Command::Export { out } => {
+            let json = export::export(&pool).await?.to_json()?;
+            match out {
+                Some(path) => std::fs::write(&path, json)
+                    .with_context(|| format!("failed to write {}", path.display()))?,
+                None => println!("{}", json),
+            }
+        }
+        Command::Import { file } => {
+            let json = std::fs::read_to_string(&file)
+                .with_context(|| format!("failed to read {}", file.display()))?;
+            let summary = export::import(&pool, &export::Export::from_json(&json)?).await?;
+            println!(
+                "Imported {} devices, {} plugins, {} mashups, {} playlists, {} playlist items, {} partials",
+                summary.devices,
+                summary.plugins,
+                summary.mashups,
+                summary.playlists,
+                summary.playlist_items,
+                summary.partials
+            );
+        }
+        Command::Gc { retention_hours } => {
+            let hours = retention_hours.unwrap_or(config.gc.retention_hours);
+            let report = gc::collect(
+                &pool,
+                storage.as_ref(),
+                Duration::from_secs(hours * 60 * 60),
+            )
+            .await?;
+            println!(
+                "Removed {} images, reclaiming {} bytes",
+                report.files_removed, report.bytes_reclaimed
+            );
+        }
+    }
+    Ok(())
+}
+
+async fn device(pool: &SqlitePool, config: &Config, command: DeviceCommand) -> Result<()> {
+    match command {
+        DeviceCommand::List => {
+            println!(
+                "{:<4} {:<17} {:<20} {:<8} {:<5} LAST SEEN",
+                "ID", "MAC", "NAME", "BATTERY", "RSSI"
+            );
+            for device in Device::all(pool).await? {
+                println!(
+                    "{:<4} {:<17} {:<20} {:<8} {:<5} {}",
+                    device.id,
+                    device.mac_address,
+                    device.name.unwrap_or_default(),
+                    device
+                        .last_battery_voltage
+                        .map(|v| format!("{:.2}V", v))
+                        .unwrap_or_default(),
+                    device
+                        .last_rssi_level
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                    device
+                        .last_seen_at
+                        .map(|v| v.to_string())
+                        .unwrap_or_else(|| "never".to_string()),
+                );
+            }
+        }
+        DeviceCommand::Add {
+            mac_address,
+            name,
+            api_key,
+        } => {
+            let mac: MacAddress = mac_address
+                .parse()
+                .with_context(|| format!("{:?} is not a MAC address", mac_address))?;
+            let api_key = api_key.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            let device = Device::create(
+                pool,
+                &mac,
+                &api_key,
+                &mac.friendly_id(),
+                &name,
+                &config.render,
+            )
+            .await?;
+            if device.api_key != api_key {
+                bail!("{} is already registered as device {}", mac, device.id);
+            }
+            println!("Added device {} ({})", device.id, mac);
+            println!("Access token: {}", device.api_key);
+        }
+        DeviceCommand::Remove { device } => {
+            let device = find_device(pool, &device).await?;
+            Device::delete(pool, device.id).await?;
+            println!("Removed device {} ({})", device.id, device.mac_address);
+        }
+        DeviceCommand::RotateKey { device } => {
+            let device = find_device(pool, &device).await?;
+            let api_key = Device::rotate_api_key(pool, device.id)
+                .await?
+                .ok_or_else(|| anyhow!("device {} disappeared", device.id))?;
+            println!("New access token for {}: {}", device.mac_address, api_key);
+        }
+    }
+    Ok(())
+}
+
+/// Look a device up by MAC address or id. MACs are tried first, since a
+/// bare-hex MAC such as `001122334455` is also a valid number.
+async fn find_device(pool: &SqlitePool, device: &str) -> Result<Device> {
+    let found = if let Ok(mac) = device.parse::<MacAddress>() {
+        Device::find_by_mac(pool, &mac).await?
+    } else if let Ok(id) = device.parse::<i64>() {
+        Device::find(pool, id).await?
+    } else {
+        bail!("{:?} is neither a device id nor a MAC address", device);
+    };
+    found.ok_or_else(|| anyhow!("no device {}", device))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_subcommand_serves() {
+        let cli = Cli::try_parse_from(["patina"]).unwrap();
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn test_parse_device_commands() {
+        let cli = Cli::try_parse_from([
+            "patina",
+            "device",
+            "add",
+            "AA:BB:CC:DD:EE:FF",
+            "--name",
+            "Hall",
+        ])
+        .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Device(DeviceCommand::Add { ref name, .. })) if name == "Hall"
+        ));
+
+        let cli =
+            Cli::try_parse_from(["patina", "--config", "x.toml", "device", "rotate-key", "3"])
+                .unwrap();
+        assert_eq!(cli.config, Some(PathBuf::from("x.toml")));
+        assert!(matches!(
+            cli.command,
+            Some(Command::Device(DeviceCommand::RotateKey { ref device })) if device == "3"
+        ));
+    }
+
+    #[test]
+    fn test_parse_render_template() {
+        let cli = Cli::try_parse_from([
+            "patina",
+            "render-template",
+            "weather.liquid",
+            "--data",
+            r#"{"temp": 21}"#,
+            "--out",
+            "preview.png",
+        ])
+        .unwrap();
+        match cli.command {
+            Some(Command::RenderTemplate {
+                file,
+                data,
+                out,
+                watch,
+                ..
+            }) => {
+                assert_eq!(file, PathBuf::from("weather.liquid"));
+                assert_eq!(data, r#"{"temp": 21}"#);
+                assert_eq!(out, PathBuf::from("preview.png"));
+                assert!(!watch);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(Cli::try_parse_from(["patina", "render-template", "x.liquid"]).is_err());
+
+        let cli =
+            Cli::try_parse_from(["patina", "preview", "x.liquid", "--out", "x.png", "--watch"])
+                .unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::RenderTemplate { watch: true, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_find_device_by_id_or_mac() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            find_device(&pool, &device.id.to_string()).await.unwrap().id,
+            device.id
+        );
+        assert_eq!(
+            find_device(&pool, "aa-bb-cc-dd-ee-ff").await.unwrap().id,
+            device.id
+        );
+        assert!(find_device(&pool, "999").await.is_err());
+        assert!(find_device(&pool, "kitchen").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_find_device_prefers_bare_hex_mac() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "00:11:22:33:44:55".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-44:55",
+            "TRMNL Device",
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+
+        // All digits, but a MAC address rather than device 1122334455
+        assert_eq!(
+            find_device(&pool, "001122334455").await.unwrap().id,
+            device.id
+        );
+    }
+}
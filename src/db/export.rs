@@ -0,0 +1,349 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+
+use crate::models::{
+    device::Device,
+    mashup::{Mashup, MashupPlugin},
+    partial::Partial,
+    playlist::{Playlist, PlaylistItem},
+    plugin::Plugin,
+};
+
+const EXPORT_VERSION: u32 = 1;
+
+/// A complete dump of the database, including device credentials. Screen
+/// history is left out: it names rendered images, which an export doesn't
+/// carry, and a restored server starts its own.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Export {
+    pub version: u32,
+    pub devices: Vec<Value>,
+    pub plugins: Vec<Value>,
+    pub playlists: Vec<Value>,
+    pub playlist_items: Vec<Value>,
+    /// Missing from exports made before partials existed
+    #[serde(default)]
+    pub partials: Vec<Value>,
+    #[serde(default)]
+    pub mashups: Vec<Value>,
+    #[serde(default)]
+    pub mashup_plugins: Vec<Value>,
+}
+
+/// How many rows of each table were written by an import
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub devices: usize,
+    pub plugins: usize,
+    pub playlists: usize,
+    pub playlist_items: usize,
+    pub partials: usize,
+    pub mashups: usize,
+    pub mashup_plugins: usize,
+}
+
+fn to_values<T: Serialize>(rows: Vec<T>) -> Result<Vec<Value>> {
+    rows.into_iter()
+        .map(|row| serde_json::to_value(row).map_err(Into::into))
+        .collect()
+}
+
+pub async fn export(pool: &SqlitePool) -> Result<Export> {
+    Ok(Export {
+        version: EXPORT_VERSION,
+        devices: to_values(Device::all(pool).await?)?,
+        plugins: to_values(Plugin::all(pool).await?)?,
+        playlists: to_values(Playlist::all(pool).await?)?,
+        playlist_items: to_values(PlaylistItem::all(pool).await?)?,
+        partials: to_values(Partial::all(pool).await?)?,
+        mashups: to_values(Mashup::all(pool).await?)?,
+        mashup_plugins: to_values(MashupPlugin::all(pool).await?)?,
+    })
+}
+
+/// Insert or update every row of an export, keyed by id, in one transaction
+pub async fn import(pool: &SqlitePool, export: &Export) -> Result<ImportSummary> {
+    if export.version != EXPORT_VERSION {
+        bail!(
+            "unsupported export version {}, expected {}",
+            export.version,
+            EXPORT_VERSION
+        );
+    }
+
+    let mut tx = pool.begin().await?;
+    // Parents first so foreign keys resolve
+    let summary = ImportSummary {
+        devices: upsert_rows(&mut tx, "devices", &export.devices).await?,
+        plugins: upsert_rows(&mut tx, "plugins", &export.plugins).await?,
+        playlists: upsert_rows(&mut tx, "playlists", &export.playlists).await?,
+        partials: upsert_rows(&mut tx, "partials", &export.partials).await?,
+        mashups: upsert_rows(&mut tx, "mashups", &export.mashups).await?,
+        mashup_plugins: upsert_rows(&mut tx, "mashup_plugins", &export.mashup_plugins).await?,
+        // Items can point at a mashup
+        playlist_items: upsert_rows(&mut tx, "playlist_items", &export.playlist_items).await?,
+    };
+    tx.commit().await?;
+    Ok(summary)
+}
+
+async fn upsert_rows(
+    tx: &mut Transaction<'_, Sqlite>,
+    table: &'static str,
+    rows: &[Value],
+) -> Result<usize> {
+    // Only ever interpolate column names the table actually has
+    let known: Vec<String> =
+        sqlx::query_scalar(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .fetch_all(&mut **tx)
+            .await?;
+
+    for (index, row) in rows.iter().enumerate() {
+        let row = row
+            .as_object()
+            .with_context(|| format!("{} row {} is not an object", table, index))?;
+        if !row.contains_key("id") {
+            bail!("{} row {} has no id", table, index);
+        }
+        let columns: Vec<&String> = row.keys().collect();
+        if let Some(unknown) = columns.iter().find(|c| !known.contains(c)) {
+            bail!("{} row {} has unknown column {}", table, index, unknown);
+        }
+
+        let sql = upsert_sql(table, &columns);
+        let mut query = sqlx::query(&sql);
+        for column in &columns {
+            query = bind_value(query, &row[column.as_str()]);
+        }
+        query
+            .execute(&mut **tx)
+            .await
+            .with_context(|| format!("failed to import {} row {}", table, index))?;
+    }
+    Ok(rows.len())
+}
+
+fn upsert_sql(table: &str, columns: &[&String]) -> String {
+    let names: Vec<&str> = columns.iter().map(|c| c.as_str()).collect();
+    let placeholders = vec!["?"; names.len()].join(", ");
+    let updates: Vec<String> = names
+        .iter()
+        .filter(|c| **c != "id")
+        .map(|c| format!("{} = excluded.{}", c, c))
+        .collect();
+    if updates.is_empty() {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (id) DO NOTHING",
+            table,
+            names.join(", "),
+            placeholders
+        )
+    } else {
+        format!(
+            "INSERT INTO {} ({}) VALUES ({}) ON CONFLICT (id) DO UPDATE SET {}",
+            table,
+            names.join(", "),
+            placeholders,
+            updates.join(", ")
+        )
+    }
+}
+
+type Query<'q> = sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>;
+
+fn bind_value<'q>(query: Query<'q>, value: &Value) -> Query<'q> {
+    match value {
+        Value::Null => query.bind(None::<String>),
+        Value::Bool(b) => query.bind(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => query.bind(i),
+            None => query.bind(n.as_f64()),
+        },
+        Value::String(s) => query.bind(normalize_timestamp(s)),
+        other => query.bind(other.to_string()),
+    }
+}
+
+/// Timestamps serialize as `2025-06-01T08:00:00`; store them the way SQLite's
+/// `datetime()` does so comparisons keep working.
+fn normalize_timestamp(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let looks_like_timestamp = bytes.len() >= 19
+        && bytes[10] == b'T'
+        && bytes[..10].iter().enumerate().all(|(i, b)| {
+            if i == 4 || i == 7 {
+                *b == b'-'
+            } else {
+                b.is_ascii_digit()
+            }
+        });
+    if looks_like_timestamp {
+        value.replacen('T', " ", 1)
+    } else {
+        value.to_string()
+    }
+}
+
+impl Export {
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> Result<Export> {
+        serde_json::from_str(json).context("invalid export file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RenderConfig,
+        db,
+        models::{
+            device::MacAddress,
+            mashup::{MashupLayout, MashupParams},
+            playlist::PlaylistParams,
+            plugin::PluginParams,
+        },
+    };
+
+    async fn seeded_pool() -> SqlitePool {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "secret",
+            "device-EE:FF",
+            "Kitchen",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Clock".to_string(),
+                render_markup: Some("<p>{{ time }}</p>".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let playlist = Playlist::create(
+            &pool,
+            &PlaylistParams {
+                device_id: device.id,
+                name: "Default".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        playlist.add_item(&pool, plugin.id).await.unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_export_import_roundtrip() {
+        let source = seeded_pool().await;
+        let json = export(&source).await.unwrap().to_json().unwrap();
+
+        let target = db::test_pool().await;
+        let summary = import(&target, &Export::from_json(&json).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                devices: 1,
+                plugins: 1,
+                playlists: 1,
+                playlist_items: 1,
+                partials: 0,
+                mashups: 0,
+                mashup_plugins: 0,
+            }
+        );
+
+        let devices = Device::all(&target).await.unwrap();
+        assert_eq!(devices[0].api_key, "secret");
+        assert_eq!(devices[0].name.as_deref(), Some("Kitchen"));
+        let created_at: String = sqlx::query_scalar("SELECT created_at FROM devices")
+            .fetch_one(&target)
+            .await
+            .unwrap();
+        assert!(!created_at.contains('T'));
+        let plugin = Plugin::all(&target).await.unwrap().pop().unwrap();
+        assert_eq!(plugin.render_markup.as_deref(), Some("<p>{{ time }}</p>"));
+
+        // Importing again updates in place
+        import(&target, &Export::from_json(&json).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(PlaylistItem::all(&target).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_export_import_mashup_items() {
+        let source = seeded_pool().await;
+        let plugin = Plugin::all(&source).await.unwrap().pop().unwrap();
+        let mashup = Mashup::create(
+            &source,
+            &MashupParams {
+                name: "Dashboard".to_string(),
+                layout: MashupLayout::OneLeftOneRight,
+                plugin_ids: vec![plugin.id, plugin.id],
+            },
+        )
+        .await
+        .unwrap();
+        let playlist = Playlist::all(&source).await.unwrap().pop().unwrap();
+        playlist.add_mashup_item(&source, mashup.id).await.unwrap();
+        let json = export(&source).await.unwrap().to_json().unwrap();
+
+        let target = db::test_pool().await;
+        import(&target, &Export::from_json(&json).unwrap())
+            .await
+            .unwrap();
+        let items = PlaylistItem::all(&target).await.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].plugin_id, Some(plugin.id));
+        assert_eq!(items[1].plugin_id, None);
+        assert_eq!(items[1].mashup_id, Some(mashup.id));
+    }
+
+    #[tokio::test]
+    async fn test_import_rejects_unknown_columns() {
+        let pool = db::test_pool().await;
+        let export = Export {
+            version: EXPORT_VERSION,
+            devices: vec![serde_json::json!({"id": 1, "name); DROP TABLE devices; --": "x"})],
+            plugins: vec![],
+            playlists: vec![],
+            playlist_items: vec![],
+            partials: vec![],
+            mashups: vec![],
+            mashup_plugins: vec![],
+        };
+
+        let err = import(&pool, &export).await.unwrap_err();
+        assert!(err.to_string().contains("unknown column"));
+    }
+
+    #[test]
+    fn test_normalize_timestamp() {
+        assert_eq!(
+            normalize_timestamp("2025-06-01T08:00:00"),
+            "2025-06-01 08:00:00"
+        );
+        assert_eq!(
+            normalize_timestamp("2025-06-01 08:00:00"),
+            "2025-06-01 08:00:00"
+        );
+        assert_eq!(normalize_timestamp("Tuesday"), "Tuesday");
+    }
+}
@@ -1,8 +1,152 @@
-use anyhow::Result;
-use sqlx::SqlitePool;
+pub mod export;
 
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use sqlx::{SqlitePool, migrate::Migrator, sqlite::SqliteConnectOptions};
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Open the database, creating the file if needed, and apply migrations
 pub async fn initialize(url: &str) -> Result<SqlitePool> {
-    let pool = SqlitePool::connect(url).await?;
-    sqlx::migrate!("./migrations").run(&pool).await?;
+    let options = SqliteConnectOptions::from_str(url)
+        .with_context(|| format!("invalid database URL {}", url))?
+        .create_if_missing(true);
+    let pool = SqlitePool::connect_with(options)
+        .await
+        .with_context(|| format!("failed to open database {}", url))?;
+    MIGRATOR
+        .run(&pool)
+        .await
+        .context("failed to apply database migrations")?;
     Ok(pool)
 }
+
+/// Versions of the bundled migrations the database hasn't applied yet
+pub async fn pending_migrations(pool: &SqlitePool) -> Result<Vec<i64>, sqlx::Error> {
+    let applied: Vec<(i64,)> =
+        sqlx::query_as("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+            .fetch_all(pool)
+            .await?;
+    Ok(MIGRATOR
+        .iter()
+        .map(|migration| migration.version)
+        .filter(|version| !applied.iter().any(|(applied,)| applied == version))
+        .collect())
+}
+
+/// Single connection in-memory database with all migrations applied.
+#[cfg(test)]
+pub async fn test_pool() -> SqlitePool {
+    let pool = sqlx::sqlite::SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap();
+    MIGRATOR.run(&pool).await.unwrap();
+    pool
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    /// In-memory database with the migrations up to and including `version`
+    /// applied, to seed rows in an older schema
+    async fn pool_at(version: i64) -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let migrations: Vec<_> = MIGRATOR
+            .iter()
+            .filter(|migration| migration.version <= version)
+            .cloned()
+            .collect();
+        Migrator {
+            migrations: Cow::Owned(migrations),
+            ..Migrator::DEFAULT
+        }
+        .run(&pool)
+        .await
+        .unwrap();
+        pool
+    }
+
+    #[tokio::test]
+    async fn test_unique_mac_migration_merges_duplicates() {
+        let pool = pool_at(20250526174702).await;
+        for mac in [
+            "aa-bb-cc-dd-ee-ff",
+            "AABBCCDDEEFF",
+            "aa:bb:cc:dd:ee:ff",
+            "001122334455",
+        ] {
+            sqlx::query(
+                "INSERT INTO devices (mac_address, api_key, image_format, default_refresh_interval, width, height, rotate)
+                 VALUES (?, 'key', 'png', 60, 800, 480, 0)",
+            )
+            .bind(mac)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        // A playlist on each duplicate of the first device
+        sqlx::query("INSERT INTO playlists (device_id, name) VALUES (2, 'Kitchen'), (3, 'Hall')")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        MIGRATOR.run(&pool).await.unwrap();
+
+        let devices: Vec<(i64, String)> =
+            sqlx::query_as("SELECT id, mac_address FROM devices ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            devices,
+            [
+                (1, "AA:BB:CC:DD:EE:FF".to_string()),
+                (4, "00:11:22:33:44:55".to_string())
+            ]
+        );
+        let playlists: Vec<(i64, String)> =
+            sqlx::query_as("SELECT device_id, name FROM playlists ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(
+            playlists,
+            [(1, "Kitchen".to_string()), (1, "Hall".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_image_format_migration_moves_png_to_auto() {
+        let pool = pool_at(20250626090000).await;
+        // Every device used to be created with png, which nothing read
+        for (mac, format) in [("AA:BB:CC:DD:EE:01", "png"), ("AA:BB:CC:DD:EE:02", "bmp")] {
+            sqlx::query(
+                "INSERT INTO devices (mac_address, api_key, image_format, default_refresh_interval, width, height, rotate)
+                 VALUES (?, 'key', ?, 60, 800, 480, 0)",
+            )
+            .bind(mac)
+            .bind(format)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        MIGRATOR.run(&pool).await.unwrap();
+
+        let formats: Vec<String> =
+            sqlx::query_scalar("SELECT image_format FROM devices ORDER BY id")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(formats, ["auto", "bmp"]);
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Serialize;
+use serde_json::{Value, json};
+use tokio::sync::Mutex;
+
+use crate::{config::Config, db, models::state::AppState, render::image::check_browser};
+
+/// Launching Chrome takes a while, so a result is reused by the probes
+/// that follow within this window
+const CHROME_CHECK_TTL: Duration = Duration::from_secs(5 * 60);
+/// Longest a single check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+static CHROME_CHECK: Mutex<Option<(Instant, Result<(), String>)>> = Mutex::const_new(None);
+
+#[derive(Serialize, Debug, PartialEq, Eq)]
+pub struct Check {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<Result<(), String>> for Check {
+    fn from(result: Result<(), String>) -> Self {
+        Check {
+            ok: result.is_ok(),
+            error: result.err(),
+        }
+    }
+}
+
+/// Liveness: answering at all means the process is up
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+/// Readiness: everything needed to serve devices is in place
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    let checks = [
+        ("database", timed(check_database(&state)).await),
+        ("migrations", timed(check_migrations(&state)).await),
+        ("assets", check_assets(&state.config).into()),
+        ("chrome", timed(check_chrome(&state.config)).await),
+    ];
+    let ready = checks.iter().all(|(_, check)| check.ok);
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let checks: serde_json::Map<String, Value> = checks
+        .into_iter()
+        .map(|(name, check)| (name.to_string(), json!(check)))
+        .collect();
+    (
+        status,
+        Json(json!({ "status": if ready { "ok" } else { "fail" }, "checks": checks })),
+    )
+}
+
+async fn timed(check: impl Future<Output = Result<(), String>>) -> Check {
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err(format!("timed out after {:?}", CHECK_TIMEOUT)))
+        .into()
+}
+
+async fn check_database(state: &AppState) -> Result<(), String> {
+    sqlx::query("SELECT 1")
+        .execute(&state.db)
+        .await
+        .map(drop)
+        .map_err(|err| err.to_string())
+}
+
+async fn check_migrations(state: &AppState) -> Result<(), String> {
+    let pending = db::pending_migrations(&state.db)
+        .await
+        .map_err(|err| err.to_string())?;
+    if pending.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("pending migrations {:?}", pending))
+    }
+}
+
+/// The files every screen needs: the setup image and the base layout. The
+/// vendored framework is optional while `base.liquid` loads it from its CDN.
+fn check_assets(config: &Config) -> Result<(), String> {
+    let required = [
+        config.paths.assets_dir.join("setup-logo.bmp"),
+        config.paths.templates_dir.join("base.liquid"),
+    ];
+    let missing: Vec<String> = required
+        .iter()
+        .filter(|path| !path.is_file())
+        .map(|path| path.display().to_string())
+        .collect();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("missing {}", missing.join(", ")))
+    }
+}
+
+async fn check_chrome(config: &Config) -> Result<(), String> {
+    let mut cached = CHROME_CHECK.lock().await;
+    if let Some((checked, result)) = cached.as_ref()
+        && checked.elapsed() < CHROME_CHECK_TTL
+    {
+        return result.clone();
+    }
+    let result = check_browser(&config.render)
+        .await
+        .map_err(|err| format!("{:#}", err));
+    *cached = Some((Instant::now(), result.clone()));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use std::{path::PathBuf, sync::Arc};
+
+    fn dirs() -> (PathBuf, PathBuf) {
+        let root = std::env::temp_dir().join(format!("patina-health-{}", uuid::Uuid::new_v4()));
+        let (assets, templates) = (root.join("assets"), root.join("templates"));
+        std::fs::create_dir_all(assets.join("framework")).unwrap();
+        std::fs::create_dir_all(&templates).unwrap();
+        std::fs::write(assets.join("setup-logo.bmp"), "BM").unwrap();
+        std::fs::write(assets.join("framework/plugins.css"), "").unwrap();
+        std::fs::write(templates.join("base.liquid"), "{{ embed }}").unwrap();
+        (assets, templates)
+    }
+
+    #[test]
+    fn test_check_assets() {
+        let (assets, templates) = dirs();
+        let mut config = Config::for_tests();
+        config.paths.assets_dir = assets.clone();
+        config.paths.templates_dir = templates;
+        assert_eq!(check_assets(&config), Ok(()));
+
+        // Not vendored yet, so not needed
+        std::fs::remove_file(assets.join("framework/plugins.css")).unwrap();
+        assert_eq!(check_assets(&config), Ok(()));
+
+        std::fs::remove_file(assets.join("setup-logo.bmp")).unwrap();
+        let err = check_assets(&config).unwrap_err();
+        assert!(err.contains("setup-logo.bmp"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_readyz_reports_each_check() {
+        let mut state = AppState::for_tests(db::test_pool().await);
+        let (assets, templates) = dirs();
+        let mut config = Config::for_tests();
+        config.paths.assets_dir = assets;
+        config.paths.templates_dir = templates;
+        state.config = Arc::new(config);
+
+        let (status, Json(body)) = readyz(State(state.clone())).await;
+        assert_eq!(body["checks"]["database"], json!({ "ok": true }));
+        assert_eq!(body["checks"]["migrations"], json!({ "ok": true }));
+        assert_eq!(body["checks"]["assets"], json!({ "ok": true }));
+        // Chrome may or may not be installed where the tests run
+        let chrome = body["checks"]["chrome"]["ok"].as_bool().unwrap();
+        assert_eq!(status == StatusCode::OK, chrome);
+
+        sqlx::query("DELETE FROM _sqlx_migrations WHERE version = (SELECT MAX(version) FROM _sqlx_migrations)")
+            .execute(&state.db)
+            .await
+            .unwrap();
+        let (status, Json(body)) = readyz(State(state)).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "fail");
+        assert_eq!(body["checks"]["migrations"]["ok"], false);
+        assert!(
+            body["checks"]["migrations"]["error"]
+                .as_str()
+                .unwrap()
+                .contains("pending")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_healthz() {
+        let Json(body) = healthz().await;
+        assert_eq!(body["status"], "ok");
+    }
+}
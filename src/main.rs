@@ -1,19 +1,34 @@
-use axum::Router;
+use anyhow::Context;
+use auth::AdminAuth;
+use axum::{Router, middleware, response::Redirect, routing::get};
+use clap::Parser;
+use cli::{Cli, Command};
+use config::Config;
 use log::info;
 use models::state::AppState;
-use std::{
-    env,
-    fs::{self, File},
-    path::Path,
-};
+use mqtt::publish::EventPublisher;
+use render::cache::TemplateCache;
+use std::{sync::Arc, time::Duration};
+use storage::signing::UrlSigner;
 use tokio::signal;
-use tower_http::{services::ServeDir, trace::TraceLayer};
+use tower_http::{
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+};
 use tracing_subscriber::EnvFilter;
 
+mod admin;
 mod api;
+mod auth;
+mod cli;
+mod config;
 mod db;
+mod health;
+mod metrics;
 mod models;
+mod mqtt;
 mod render;
+mod storage;
 
 async fn shutdown_signal() {
     let ctrl_c = async {
@@ -45,47 +60,250 @@ async fn main() -> anyhow::Result<()> {
         )
         .init();
 
-    let assets_path = Path::new("./assets/setup-logo.bmp");
-    if !assets_path.exists() {
-        panic!("Missing asset path");
+    let cli = Cli::parse();
+    let config = Config::load(cli.config.as_deref()).context("invalid configuration")?;
+
+    match cli.command {
+        None | Some(Command::Serve) => serve(config).await,
+        Some(command) => cli::run(command, &config).await,
     }
+}
+
+async fn serve(config: Config) -> anyhow::Result<()> {
+    let pool = db::initialize(&config.database_url).await?;
+    info!("DB created");
 
-    let assets_base_path = Path::new("./assets/images/generated");
-    if !assets_base_path.exists() {
-        fs::create_dir_all(assets_base_path)?;
+    let http = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let config = Arc::new(config);
+    let templates = TemplateCache::new(&config.paths.templates_dir, config.dev_mode);
+    let storage = storage::from_config(&config)?;
+
+    let mut events = None;
+    if let Some(mqtt_url) = &config.mqtt.url {
+        let client_id = mqtt::client_id(&config.mqtt);
+        let options = mqtt::options_from_url(mqtt_url, &format!("{}-subscriber", client_id))?;
+        tokio::spawn(mqtt::subscribe::run(
+            pool.clone(),
+            config.clone(),
+            templates.clone(),
+            storage.clone(),
+            options,
+        ));
+        info!("MQTT subscriptions enabled");
+
+        let options = mqtt::options_from_url(mqtt_url, &format!("{}-publisher", client_id))?;
+        events = Some(EventPublisher::connect(
+            options,
+            &config.mqtt.topic_prefix,
+            &config.mqtt.discovery_prefix,
+        ));
+        info!("MQTT device events enabled");
     }
 
-    let db_path = Path::new("./database.db");
-    // Create a new DB if it doesn't exist
-    if !db_path.exists() {
-        File::create(db_path)?;
+    if config.gc.interval_minutes > 0 {
+        tokio::spawn(render::gc::run(
+            pool.clone(),
+            config.clone(),
+            storage.clone(),
+            metrics::METRICS.gc.clone(),
+        ));
     }
-    let pool = db::initialize(db_path.to_str().unwrap()).await;
-    info!("DB created");
-    let pool = match pool {
-        Ok(pool) => pool,
-        Err(err) => panic!("{}", err),
-    };
-    let port = env::var("PORT").unwrap_or("3000".to_string());
-    let base_url = env::var("BASE_URL").unwrap_or(format!("http://localhost:{}", port));
-    let bind_url = format!("0.0.0.0:{}", port);
 
+    let signer = UrlSigner::from_config(&config);
+    let admin = AdminAuth::from_config(&config);
     let state = AppState {
         db: pool,
-        base_url: base_url.clone(),
+        config: config.clone(),
+        http,
+        events,
+        templates,
+        storage,
+        signer,
+        admin,
     };
 
-    let app = Router::new()
-        .nest("/api", api::router())
-        .nest_service("/storage/images", ServeDir::new("assets"))
-        .layer(TraceLayer::new_for_http())
-        .with_state(state);
-    let listener = tokio::net::TcpListener::bind(&bind_url).await?;
-    info!("🚀 TRMNL BYOS Server running on {}", &base_url);
+    let listener = tokio::net::TcpListener::bind(&config.bind_address)
+        .await
+        .with_context(|| format!("failed to bind {}", config.bind_address))?;
+    info!("🚀 TRMNL BYOS Server running on {}", &config.base_url);
 
-    axum::serve(listener, app)
+    axum::serve(listener, app(state))
         .with_graceful_shutdown(shutdown_signal())
         .await?;
     info!("byos-rust Shutdown!");
     Ok(())
 }
+
+/// Every route the server answers, with the auth and metrics layers applied
+fn app(state: AppState) -> Router {
+    let require_admin = middleware::from_fn_with_state(state.clone(), auth::require_admin);
+    Router::new()
+        .route("/", get(|| async { Redirect::to("/admin") }))
+        .nest("/api", api::router())
+        .nest(
+            "/api/devices",
+            api::devices::router().route_layer(require_admin.clone()),
+        )
+        .nest("/admin", admin::router().route_layer(require_admin.clone()))
+        .route(
+            "/storage/images/generated/{key}",
+            get(storage::serve_image).route_layer(middleware::from_fn_with_state(
+                state.clone(),
+                storage::signing::require_signature,
+            )),
+        )
+        // Only the public assets; rendered screens may live below assets_dir
+        // too and are served signed above
+        .route_service(
+            "/storage/images/setup-logo.bmp",
+            ServeFile::new(state.config.paths.assets_dir.join("setup-logo.bmp")),
+        )
+        .nest_service(
+            "/storage/images/framework",
+            ServeDir::new(state.config.paths.assets_dir.join("framework")),
+        )
+        .route("/metrics", get(metrics::metrics_endpoint))
+        .route_layer(middleware::from_fn(metrics::track_requests))
+        // Probes are left out of the request metrics
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .layer(TraceLayer::new_for_http())
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode, header},
+    };
+    use tower::ServiceExt;
+
+    async fn status(app: &Router, request: Request<Body>) -> StatusCode {
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_device_api_requires_admin() {
+        let app = app(AppState::for_tests(db::test_pool().await));
+
+        for (method, uri) in [
+            ("GET", "/api/devices"),
+            ("GET", "/api/devices/1"),
+            ("PATCH", "/api/devices/1"),
+            ("DELETE", "/api/devices/1"),
+            ("GET", "/api/devices/1/screen"),
+            ("GET", "/api/devices/1/screens"),
+        ] {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            assert_eq!(
+                status(&app, request).await,
+                StatusCode::UNAUTHORIZED,
+                "{} {}",
+                method,
+                uri
+            );
+        }
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/api/devices").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let request = Request::get("/api/devices")
+            .header(header::AUTHORIZATION, "Bearer test admin password")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::OK);
+
+        // Devices still check in with their own access token
+        let request = Request::get("/api/setup")
+            .header("ID", "AA:BB:CC:DD:EE:FF")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_storage_serves_only_public_assets() {
+        let assets = std::env::temp_dir().join(format!("patina-assets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(assets.join("framework")).unwrap();
+        std::fs::create_dir_all(assets.join("images/generated")).unwrap();
+        std::fs::write(assets.join("setup-logo.bmp"), "BM").unwrap();
+        std::fs::write(assets.join("framework/plugins.css"), "body {}").unwrap();
+        std::fs::write(assets.join("images/generated/abc.png"), "png").unwrap();
+        let mut state = AppState::for_tests(db::test_pool().await);
+        let mut config = Config::for_tests();
+        config.paths.assets_dir = assets.clone();
+        config.paths.generated_dir = assets.join("images/generated");
+        state.config = Arc::new(config);
+        let app = app(state);
+
+        for (uri, expected) in [
+            ("/storage/images/setup-logo.bmp", StatusCode::OK),
+            ("/storage/images/framework/plugins.css", StatusCode::OK),
+            // Generated screens only with a signature
+            (
+                "/storage/images/images/generated/abc.png",
+                StatusCode::NOT_FOUND,
+            ),
+            ("/storage/images/generated/abc.png", StatusCode::FORBIDDEN),
+        ] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            assert_eq!(status(&app, request).await, expected, "{}", uri);
+        }
+        std::fs::remove_dir_all(&assets).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_admin_requires_login_and_same_origin_posts() {
+        let app = app(AppState::for_tests(db::test_pool().await));
+        let login = "Bearer test admin password";
+
+        let request = Request::get("/admin").body(Body::empty()).unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::UNAUTHORIZED);
+        let request = Request::post("/admin/plugins/1/delete")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::UNAUTHORIZED);
+
+        let request = Request::get("/admin")
+            .header(header::AUTHORIZATION, login)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::OK);
+
+        // A form on another site posting with the browser's saved login
+        let request = Request::post("/admin/plugins/1/delete")
+            .header(header::AUTHORIZATION, login)
+            .header(header::HOST, "localhost:3000")
+            .header(header::ORIGIN, "https://evil.example.com")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::FORBIDDEN);
+        let request = Request::delete("/api/devices/1")
+            .header(header::AUTHORIZATION, login)
+            .header(header::ORIGIN, "https://evil.example.com")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(status(&app, request).await, StatusCode::FORBIDDEN);
+
+        let request = Request::post("/admin/plugins/1/delete")
+            .header(header::AUTHORIZATION, login)
+            .header(header::HOST, "localhost:3000")
+            .header(header::ORIGIN, "http://localhost:3000")
+            .body(Body::empty())
+            .unwrap();
+        let status = status(&app, request).await;
+        assert!(status.is_redirection(), "{}", status);
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Arc, LazyLock, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use log::warn;
+
+use crate::{
+    models::{device::Device, state::AppState},
+    render::gc::GcMetrics,
+};
+
+/// Counters shared by everything in the process, exported at `/metrics` in
+/// the Prometheus text format. Rendering happens from the API, MQTT and the
+/// CLI alike, so this is a global rather than part of `AppState`.
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::default);
+
+/// Upper bounds in seconds, covering both quick API calls and slow renders
+const BUCKETS: [f64; 12] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Default)]
+pub struct Metrics {
+    /// Responses by method, matched route and status
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    request_durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    renders: Mutex<Histogram>,
+    render_failures: AtomicU64,
+    browsers_running: AtomicU64,
+    browser_launches: AtomicU64,
+    /// `/api/display` calls by device MAC address
+    check_ins: Mutex<BTreeMap<String, u64>>,
+    /// Upstream fetches and MQTT updates by source and outcome
+    polls: Mutex<BTreeMap<(&'static str, &'static str), u64>>,
+    pub gc: Arc<GcMetrics>,
+}
+
+#[derive(Clone, Default)]
+struct Histogram {
+    /// Observations per bucket, not yet cumulative
+    counts: [u64; BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.counts[i] += 1;
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"+Inf\"}} {}",
+            name, labels, self.count
+        );
+        let labels = match labels.trim_end_matches(',') {
+            "" => String::new(),
+            labels => format!("{{{}}}", labels),
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+/// Counts a running browser until dropped
+pub struct BrowserGuard<'a>(&'a Metrics);
+
+impl Drop for BrowserGuard<'_> {
+    fn drop(&mut self) {
+        self.0.browsers_running.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    // Every update is a single increment, so a panic can't leave one half done
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Escape a label value as the text format requires
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+impl Metrics {
+    pub fn record_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        *lock(&self.requests)
+            .entry((method.to_string(), route.to_string(), status))
+            .or_default() += 1;
+        lock(&self.request_durations)
+            .entry((method.to_string(), route.to_string()))
+            .or_default()
+            .observe(elapsed);
+    }
+
+    pub fn record_render(&self, elapsed: Duration, succeeded: bool) {
+        if succeeded {
+            lock(&self.renders).observe(elapsed);
+        } else {
+            self.render_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Note a browser launch; it counts as running until the guard is dropped
+    pub fn browser_started(&self) -> BrowserGuard<'_> {
+        self.browser_launches.fetch_add(1, Ordering::Relaxed);
+        self.browsers_running.fetch_add(1, Ordering::Relaxed);
+        BrowserGuard(self)
+    }
+
+    pub fn record_check_in(&self, mac_address: &str) {
+        *lock(&self.check_ins)
+            .entry(mac_address.to_string())
+            .or_default() += 1;
+    }
+
+    /// Count a fetch from `source`, `cloud` for the upstream proxy or `mqtt`
+    /// for subscribed plugin data
+    pub fn record_poll(&self, source: &'static str, succeeded: bool) {
+        let result = if succeeded { "success" } else { "failure" };
+        *lock(&self.polls).entry((source, result)).or_default() += 1;
+    }
+
+    /// Everything in the Prometheus text format, with battery and signal
+    /// gauges for `devices`
+    pub fn render(&self, devices: &[Device]) -> String {
+        let mut out = String::new();
+
+        family(
+            &mut out,
+            "patina_http_requests_total",
+            "counter",
+            "HTTP responses by route and status.",
+        );
+        for ((method, route, status), count) in lock(&self.requests).iter() {
+            let _ = writeln!(
+                out,
+                "patina_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                method,
+                label(route),
+                status,
+                count
+            );
+        }
+        family(
+            &mut out,
+            "patina_http_request_duration_seconds",
+            "histogram",
+            "Time taken to answer HTTP requests.",
+        );
+        for ((method, route), histogram) in lock(&self.request_durations).iter() {
+            let labels = format!("method=\"{}\",route=\"{}\",", method, label(route));
+            histogram.write(&mut out, "patina_http_request_duration_seconds", &labels);
+        }
+
+        family(
+            &mut out,
+            "patina_render_duration_seconds",
+            "histogram",
+            "Time taken to screenshot a screen in Chrome.",
+        );
+        lock(&self.renders).write(&mut out, "patina_render_duration_seconds", "");
+        family(
+            &mut out,
+            "patina_render_failures_total",
+            "counter",
+            "Screens that failed to render.",
+        );
+        let _ = writeln!(
+            out,
+            "patina_render_failures_total {}",
+            self.render_failures.load(Ordering::Relaxed)
+        );
+        family(
+            &mut out,
+            "patina_chrome_browsers_running",
+            "gauge",
+            "Chrome instances currently rendering.",
+        );
+        let _ = writeln!(
+            out,
+            "patina_chrome_browsers_running {}",
+            self.browsers_running.load(Ordering::Relaxed)
+        );
+        family(
+            &mut out,
+            "patina_chrome_launches_total",
+            "counter",
+            "Chrome instances started.",
+        );
+        let _ = writeln!(
+            out,
+            "patina_chrome_launches_total {}",
+            self.browser_launches.load(Ordering::Relaxed)
+        );
+
+        family(
+            &mut out,
+            "patina_device_check_ins_total",
+            "counter",
+            "Display requests by device.",
+        );
+        for (mac, count) in lock(&self.check_ins).iter() {
+            let _ = writeln!(
+                out,
+                "patina_device_check_ins_total{{mac=\"{}\"}} {}",
+                label(mac),
+                count
+            );
+        }
+        family(
+            &mut out,
+            "patina_device_battery_volts",
+            "gauge",
+            "Battery voltage last reported by each device.",
+        );
+        for device in devices {
+            if let Some(voltage) = device.last_battery_voltage {
+                let _ = writeln!(
+                    out,
+                    "patina_device_battery_volts{{mac=\"{}\",name=\"{}\"}} {}",
+                    label(&device.mac_address),
+                    label(device.name.as_deref().unwrap_or_default()),
+                    voltage
+                );
+            }
+        }
+        family(
+            &mut out,
+            "patina_device_rssi_dbm",
+            "gauge",
+            "WiFi signal strength last reported by each device.",
+        );
+        for device in devices {
+            if let Some(rssi) = device.last_rssi_level {
+                let _ = writeln!(
+                    out,
+                    "patina_device_rssi_dbm{{mac=\"{}\",name=\"{}\"}} {}",
+                    label(&device.mac_address),
+                    label(device.name.as_deref().unwrap_or_default()),
+                    rssi
+                );
+            }
+        }
+
+        family(
+            &mut out,
+            "patina_polls_total",
+            "counter",
+            "Cloud proxy fetches and MQTT data updates by outcome.",
+        );
+        for ((source, result), count) in lock(&self.polls).iter() {
+            let _ = writeln!(
+                out,
+                "patina_polls_total{{source=\"{}\",result=\"{}\"}} {}",
+                source, result, count
+            );
+        }
+
+        for (name, help, value) in [
+            (
+                "patina_gc_runs_total",
+                "Image cleanups completed.",
+                &self.gc.runs,
+            ),
+            (
+                "patina_gc_files_removed_total",
+                "Unreferenced images deleted.",
+                &self.gc.files_removed,
+            ),
+            (
+                "patina_gc_bytes_reclaimed_total",
+                "Bytes freed by deleting unreferenced images.",
+                &self.gc.bytes_reclaimed,
+            ),
+        ] {
+            family(&mut out, name, "counter", help);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+/// Count and time every request by the route it matched
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+    let response = next.run(request).await;
+    METRICS.record_request(
+        &method,
+        &route,
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+pub async fn metrics_endpoint(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let devices = Device::all(&state.db).await.map_err(|err| {
+        warn!("failed to load devices for metrics: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        METRICS.render(&devices),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::RenderConfig, db, models::device::MacAddress};
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(20));
+        histogram.observe(Duration::from_millis(300));
+        histogram.observe(Duration::from_secs(60));
+
+        let mut out = String::new();
+        histogram.write(&mut out, "t", "route=\"/\",");
+        assert!(out.contains("t_bucket{route=\"/\",le=\"0.01\"} 0\n"));
+        assert!(out.contains("t_bucket{route=\"/\",le=\"0.025\"} 1\n"));
+        assert!(out.contains("t_bucket{route=\"/\",le=\"0.5\"} 2\n"));
+        assert!(out.contains("t_bucket{route=\"/\",le=\"30\"} 2\n"));
+        assert!(out.contains("t_bucket{route=\"/\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("t_count{route=\"/\"} 3\n"));
+    }
+
+    #[tokio::test]
+    async fn test_render() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "Kitchen \"left\"",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        sqlx::query(
+            "UPDATE devices SET last_battery_voltage = 3.9, last_rssi_level = -55 WHERE id = ?",
+        )
+        .bind(device.id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let metrics = Metrics::default();
+        metrics.record_request("GET", "/api/display", 200, Duration::from_millis(3));
+        metrics.record_render(Duration::from_secs(2), true);
+        metrics.record_render(Duration::from_secs(2), false);
+        metrics.record_check_in("AA:BB:CC:DD:EE:FF");
+        metrics.record_poll("cloud", false);
+        drop(metrics.browser_started());
+        let _running = metrics.browser_started();
+
+        let out = metrics.render(&Device::all(&pool).await.unwrap());
+        for line in [
+            "patina_http_requests_total{method=\"GET\",route=\"/api/display\",status=\"200\"} 1",
+            "patina_http_request_duration_seconds_count{method=\"GET\",route=\"/api/display\"} 1",
+            "patina_render_duration_seconds_count 1",
+            "patina_render_failures_total 1",
+            "patina_chrome_browsers_running 1",
+            "patina_chrome_launches_total 2",
+            "patina_device_check_ins_total{mac=\"AA:BB:CC:DD:EE:FF\"} 1",
+            "patina_device_battery_volts{mac=\"AA:BB:CC:DD:EE:FF\",name=\"Kitchen \\\"left\\\"\"} 3.9",
+            "patina_device_rssi_dbm{mac=\"AA:BB:CC:DD:EE:FF\",name=\"Kitchen \\\"left\\\"\"} -55",
+            "patina_polls_total{source=\"cloud\",result=\"failure\"} 1",
+            "patina_gc_runs_total 0",
+        ] {
+            assert!(out.contains(&format!("{}\n", line)), "missing {}", line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_endpoint_counts_matched_routes() {
+        let state = AppState::for_tests(db::test_pool().await);
+        let app = Router::new()
+            .route("/metrics", get(metrics_endpoint))
+            .route("/items/{id}", get(|| async { "item" }))
+            .route_layer(middleware::from_fn(track_requests))
+            .with_state(state);
+
+        app.clone()
+            .oneshot(Request::get("/items/7").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(
+            response.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain")
+        );
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("route=\"/items/{id}\",status=\"200\""));
+    }
+}
@@ -1,7 +1,84 @@
+use std::{fmt, str::FromStr};
+
 use chrono::NaiveDateTime;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
+
+use sqlx::prelude::*;
+
+use crate::{config::RenderConfig, render::image::ScreenFormat};
+
+/// A validated hardware address. Parses colon, dash and bare hex notation in
+/// any case and always displays as upper-case, colon separated octets, which
+/// is the form stored in the `devices` table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MacAddress([u8; 6]);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseMacAddressError;
+
+impl fmt::Display for ParseMacAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid MAC address")
+    }
+}
+
+impl std::error::Error for ParseMacAddressError {}
+
+impl MacAddress {
+    /// Derive the default friendly id from the last two octets,
+    /// e.g. `AA:BB:CC:DD:EE:FF` becomes `device-EE:FF`.
+    pub fn friendly_id(&self) -> String {
+        format!("device-{:02X}:{:02X}", self.0[4], self.0[5])
+    }
+}
+
+impl FromStr for MacAddress {
+    type Err = ParseMacAddressError;
 
-use sqlx::{prelude::*, sqlite::SqliteQueryResult};
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if !s.is_ascii() {
+            return Err(ParseMacAddressError);
+        }
+        let hex: String = match s.len() {
+            12 => s.to_string(),
+            17 => {
+                let bytes = s.as_bytes();
+                let sep = bytes[2];
+                if sep != b':' && sep != b'-' {
+                    return Err(ParseMacAddressError);
+                }
+                // Separators must sit between every octet and be consistent
+                if (2..17).step_by(3).any(|i| bytes[i] != sep) {
+                    return Err(ParseMacAddressError);
+                }
+                s.split(sep as char).collect()
+            }
+            _ => return Err(ParseMacAddressError),
+        };
+
+        let mut octets = [0u8; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            let pair = &hex[i * 2..i * 2 + 2];
+            if !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(ParseMacAddressError);
+            }
+            *octet = u8::from_str_radix(pair, 16).map_err(|_| ParseMacAddressError)?;
+        }
+        Ok(MacAddress(octets))
+    }
+}
+
+impl fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(
+            f,
+            "{:02X}:{:02X}:{:02X}:{:02X}:{:02X}:{:02X}",
+            a, b, c, d, e, g
+        )
+    }
+}
 
 #[derive(FromRow, Serialize, Deserialize, Clone)]
 pub struct Device {
@@ -20,74 +97,393 @@ pub struct Device {
     pub height: i32,
     pub rotate: i32,
     pub image_format: String,
+    pub model: Option<String>,
+    pub last_refresh_rate: Option<i32>,
+    pub last_seen_at: Option<NaiveDateTime>,
+    pub cloud_api_key: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
+/// Values reported by the firmware in the headers of a check-in. Fields the
+/// device did not send are left untouched in the database.
+#[derive(Debug, Default, Clone)]
+pub struct DeviceTelemetry {
+    pub rssi: Option<i32>,
+    pub battery_voltage: Option<f64>,
+    pub firmware_version: Option<String>,
+    pub refresh_rate: Option<i32>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub model: Option<String>,
+}
+
+/// Editable device settings; `None` leaves the stored value unchanged. The
+/// nullable columns take `Some(None)`, sent as `null`, to clear them.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct DeviceUpdate {
+    #[serde(default, deserialize_with = "nullable")]
+    pub name: Option<Option<String>>,
+    pub default_refresh_interval: Option<i32>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub rotate: Option<i32>,
+    pub image_format: Option<String>,
+    pub proxy_cloud: Option<bool>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub cloud_api_key: Option<Option<String>>,
+}
+
+/// Tell a field sent as `null` apart from one left out, which serde would
+/// otherwise both read as `None`
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl DeviceUpdate {
+    /// Check the requested values are something a device can display
+    pub fn validate(&self) -> Result<(), String> {
+        if self.default_refresh_interval.is_some_and(|i| i <= 0) {
+            return Err("default_refresh_interval must be positive".to_string());
+        }
+        if self.width.is_some_and(|w| w <= 0) || self.height.is_some_and(|h| h <= 0) {
+            return Err("width and height must be positive".to_string());
+        }
+        if self.rotate.is_some_and(|r| ![0, 90, 180, 270].contains(&r)) {
+            return Err("rotate must be one of 0, 90, 180 or 270".to_string());
+        }
+        if let Some(format) = &self.image_format
+            && format != "auto"
+            && ScreenFormat::from_name(format).is_none()
+        {
+            return Err("image_format must be auto, png or bmp".to_string());
+        }
+        Ok(())
+    }
+}
+
 impl Device {
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<Device>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM devices ORDER BY id")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<Device>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM devices WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn update(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        update: &DeviceUpdate,
+    ) -> Result<Option<Device>, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE devices SET name = IIF(?, ?, name), default_refresh_interval = COALESCE(?, default_refresh_interval), width = COALESCE(?, width), height = COALESCE(?, height), custom_geometry = (custom_geometry OR COALESCE(?, ?) IS NOT NULL), rotate = COALESCE(?, rotate), image_format = COALESCE(?, image_format), proxy_cloud = COALESCE(?, proxy_cloud), cloud_api_key = IIF(?, ?, cloud_api_key), updated_at = datetime('now') WHERE id = ? RETURNING *"
+        )
+        .bind(update.name.is_some())
+        .bind(update.name.clone().flatten())
+        .bind(update.default_refresh_interval)
+        .bind(update.width)
+        .bind(update.height)
+        .bind(update.width)
+        .bind(update.height)
+        .bind(update.rotate)
+        .bind(&update.image_format)
+        .bind(update.proxy_cloud)
+        .bind(update.cloud_api_key.is_some())
+        .bind(update.cloud_api_key.clone().flatten())
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Replace the device's access token, returning the new one
+    pub async fn rotate_api_key(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let api_key = uuid::Uuid::new_v4().to_string();
+        let result = sqlx::query(
+            "UPDATE devices SET api_key = ?, updated_at = datetime('now') WHERE id = ?",
+        )
+        .bind(&api_key)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok((result.rows_affected() > 0).then_some(api_key))
+    }
+
+    /// Show `image` on the device from its next check-in, returning the
+    /// refreshed row
+    pub async fn set_current_screen_image(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        image: &str,
+    ) -> Result<Device, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE devices SET current_screen_image = ?, updated_at = datetime('now') WHERE id = ? RETURNING *",
+        )
+        .bind(image)
+        .bind(id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Returns whether a device was removed
+    pub async fn delete(pool: &sqlx::SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM devices WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
     pub async fn find_by_credentials(
         pool: &sqlx::SqlitePool,
-        mac_address: &str,
+        mac_address: &MacAddress,
         api_key: &str,
     ) -> Result<Option<Device>, sqlx::Error> {
         sqlx::query_as("SELECT * FROM devices WHERE mac_address = ? AND api_key = ?")
-            .bind(mac_address)
+            .bind(mac_address.to_string())
             .bind(api_key)
             .fetch_optional(pool)
             .await
     }
 
+    /// Record a check-in, storing whatever telemetry the device reported, and
+    /// return the refreshed row. A width and height set through `update`
+    /// take precedence over the reported ones.
     pub async fn update_device_info(
         pool: &sqlx::SqlitePool,
-        rssi: i32,
-        bat_volt: f64,
-        fw_version: &str,
-    ) -> Result<SqliteQueryResult, sqlx::Error> {
-        sqlx::query("UPDATE devices SET last_rssi_level = ?, last_battery_voltage = ?, last_firmware_version = ?, updated_at CURRENT_TIMESTAMP WHERE id = ?")
-            .bind(rssi)
-            .bind(bat_volt)
-            .bind(fw_version)
-            .execute(pool)
-            .await
+        id: i64,
+        telemetry: &DeviceTelemetry,
+    ) -> Result<Device, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE devices SET last_rssi_level = COALESCE(?, last_rssi_level), last_battery_voltage = COALESCE(?, last_battery_voltage), last_firmware_version = COALESCE(?, last_firmware_version), last_refresh_rate = COALESCE(?, last_refresh_rate), width = IIF(custom_geometry, width, COALESCE(?, width)), height = IIF(custom_geometry, height, COALESCE(?, height)), model = COALESCE(?, model), last_seen_at = datetime('now'), updated_at = datetime('now') WHERE id = ? RETURNING *"
+        )
+        .bind(telemetry.rssi)
+        .bind(telemetry.battery_voltage)
+        .bind(&telemetry.firmware_version)
+        .bind(telemetry.refresh_rate)
+        .bind(telemetry.width)
+        .bind(telemetry.height)
+        .bind(&telemetry.model)
+        .bind(id)
+        .fetch_one(pool)
+        .await
     }
 
     pub async fn find_by_mac(
         pool: &sqlx::SqlitePool,
-        mac_address: &str,
+        mac_address: &MacAddress,
     ) -> Result<Option<Device>, sqlx::Error> {
         sqlx::query_as("SELECT * FROM devices WHERE mac_address = ?")
-            .bind(mac_address)
+            .bind(mac_address.to_string())
             .fetch_optional(pool)
             .await
     }
 
+    /// Register a device, or return the already persisted row when the MAC
+    /// address is known. Existing credentials are never overwritten.
     pub async fn create(
         pool: &sqlx::SqlitePool,
-        mac_address: &str,
+        mac_address: &MacAddress,
         api_key: &str,
         friendly_id: &str,
         name: &str,
-    ) -> Result<SqliteQueryResult, sqlx::Error> {
-        sqlx::query(
-            "INSERT INTO devices (mac_address, api_key, friendly_id, name, image_format, default_refresh_interval, width, height, rotate, proxy_cloud, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now'))"
+        defaults: &RenderConfig,
+    ) -> Result<Device, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO devices (mac_address, api_key, friendly_id, name, image_format, default_refresh_interval, width, height, rotate, proxy_cloud, created_at, updated_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, datetime('now'), datetime('now')) ON CONFLICT (mac_address) DO UPDATE SET updated_at = datetime('now') RETURNING *"
         )
-        .bind(mac_address)
+        .bind(mac_address.to_string())
         .bind(api_key)
         .bind(friendly_id)
         .bind(name)
         // image format
-        .bind("png")
+        .bind(&defaults.image_format)
         // refresh interval
-        .bind(60)
+        .bind(defaults.refresh_interval)
         // width
-        .bind(800)
+        .bind(defaults.width)
         // height
-        .bind(480)
+        .bind(defaults.height)
         // rotate
         .bind(0)
         // proxy cloud
         .bind(false)
-        .execute(pool)
+        .fetch_one(pool)
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    const EXPECTED: [u8; 6] = [0xAA, 0xBB, 0xCC, 0x0D, 0xEE, 0xFF];
+
+    #[test]
+    fn test_mac_address_colon_format() {
+        let mac: MacAddress = "AA:BB:CC:0D:EE:FF".parse().unwrap();
+        assert_eq!(mac, MacAddress(EXPECTED));
+    }
+
+    #[test]
+    fn test_mac_address_dash_format() {
+        let mac: MacAddress = "AA-BB-CC-0D-EE-FF".parse().unwrap();
+        assert_eq!(mac, MacAddress(EXPECTED));
+    }
+
+    #[test]
+    fn test_mac_address_bare_hex_format() {
+        let mac: MacAddress = "AABBCC0DEEFF".parse().unwrap();
+        assert_eq!(mac, MacAddress(EXPECTED));
+    }
+
+    #[test]
+    fn test_mac_address_lower_and_mixed_case() {
+        let lower: MacAddress = "aa:bb:cc:0d:ee:ff".parse().unwrap();
+        let mixed: MacAddress = "aA-bB-Cc-0d-eE-Ff".parse().unwrap();
+        assert_eq!(lower, MacAddress(EXPECTED));
+        assert_eq!(mixed, MacAddress(EXPECTED));
+    }
+
+    #[test]
+    fn test_mac_address_normalizes_display() {
+        let mac: MacAddress = "aabbcc0deeff".parse().unwrap();
+        assert_eq!(mac.to_string(), "AA:BB:CC:0D:EE:FF");
+    }
+
+    #[test]
+    fn test_mac_address_friendly_id() {
+        let mac: MacAddress = "aa-bb-cc-0d-ee-ff".parse().unwrap();
+        assert_eq!(mac.friendly_id(), "device-EE:FF");
+    }
+
+    #[test]
+    fn test_mac_address_rejects_garbage() {
+        for input in [
+            "",
+            "AA:BB",
+            "not a mac address",
+            "GG:BB:CC:DD:EE:FF",
+            "AA:BB-CC:DD:EE:FF",
+            "AA.BB.CC.DD.EE.FF",
+            "AABBCCDDEEF",
+            "AA:BB:CC:DD:EE:FF:00",
+            "AAB:BCC:DDE:EFF:0:0",
+            "+A:BB:CC:DD:EE:FF",
+            "ÄÄ:BB:CC:DD:EE",
+            "ÄÄBBCCDDEE",
+        ] {
+            assert_eq!(
+                input.parse::<MacAddress>(),
+                Err(ParseMacAddressError),
+                "{input}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_is_idempotent() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:0D:EE:FF".parse().unwrap();
+
+        let first = Device::create(
+            &pool,
+            &mac,
+            "first-key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let second = Device::create(
+            &pool,
+            &mac,
+            "second-key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(first.id, second.id);
+        assert_eq!(second.api_key, "first-key");
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM devices")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_mac_address_is_unique() {
+        let pool = db::test_pool().await;
+        let insert = "INSERT INTO devices (mac_address, api_key, image_format, default_refresh_interval, width, height, rotate) VALUES ('AA:BB:CC:0D:EE:FF', 'key', 'png', 60, 800, 480, 0)";
+
+        sqlx::query(insert).execute(&pool).await.unwrap();
+        assert!(sqlx::query(insert).execute(&pool).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_custom_geometry_survives_check_in() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:0D:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "TRMNL Device",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let reported = DeviceTelemetry {
+            width: Some(1872),
+            height: Some(1404),
+            ..Default::default()
+        };
+
+        // Until edited, the device's own report wins
+        let device = Device::update_device_info(&pool, device.id, &reported)
+            .await
+            .unwrap();
+        assert_eq!((device.width, device.height), (1872, 1404));
+
+        let update = DeviceUpdate {
+            width: Some(1404),
+            height: Some(1872),
+            ..Default::default()
+        };
+        Device::update(&pool, device.id, &update).await.unwrap();
+        let device = Device::update_device_info(&pool, device.id, &reported)
+            .await
+            .unwrap();
+        assert_eq!((device.width, device.height), (1404, 1872));
+
+        // Renaming later doesn't give the geometry back to the firmware
+        let rename = DeviceUpdate {
+            name: Some(Some("Hall".to_string())),
+            ..Default::default()
+        };
+        Device::update(&pool, device.id, &rename).await.unwrap();
+        let device = Device::update_device_info(&pool, device.id, &reported)
+            .await
+            .unwrap();
+        assert_eq!((device.width, device.height), (1404, 1872));
+    }
+}
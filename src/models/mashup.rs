@@ -0,0 +1,298 @@
+use std::{fmt, str::FromStr};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::*;
+
+use crate::models::plugin::{Plugin, View};
+
+/// How plugins are arranged on a mashup screen, named as in the TRMNL
+/// framework's `mashup--*` classes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MashupLayout {
+    /// Two halves side by side
+    OneLeftOneRight,
+    /// Two halves stacked
+    OneTopOneBottom,
+    /// A half on the left, two quadrants on the right
+    OneLeftTwoRight,
+    /// Two quadrants on the left, a half on the right
+    TwoLeftOneRight,
+    /// A half on top, two quadrants below
+    OneTopTwoBottom,
+    /// Two quadrants on top, a half below
+    TwoTopOneBottom,
+    /// Four quadrants
+    Quadrants,
+}
+
+impl MashupLayout {
+    pub const ALL: [MashupLayout; 7] = [
+        MashupLayout::OneLeftOneRight,
+        MashupLayout::OneTopOneBottom,
+        MashupLayout::OneLeftTwoRight,
+        MashupLayout::TwoLeftOneRight,
+        MashupLayout::OneTopTwoBottom,
+        MashupLayout::TwoTopOneBottom,
+        MashupLayout::Quadrants,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MashupLayout::OneLeftOneRight => "1Lx1R",
+            MashupLayout::OneTopOneBottom => "1Tx1B",
+            MashupLayout::OneLeftTwoRight => "1Lx2R",
+            MashupLayout::TwoLeftOneRight => "2Lx1R",
+            MashupLayout::OneTopTwoBottom => "1Tx2B",
+            MashupLayout::TwoTopOneBottom => "2Tx1B",
+            MashupLayout::Quadrants => "2x2",
+        }
+    }
+
+    /// The view each slot is drawn at, in document order
+    pub fn views(&self) -> &'static [View] {
+        use View::*;
+        match self {
+            MashupLayout::OneLeftOneRight => &[HalfVertical, HalfVertical],
+            MashupLayout::OneTopOneBottom => &[HalfHorizontal, HalfHorizontal],
+            MashupLayout::OneLeftTwoRight => &[HalfVertical, Quadrant, Quadrant],
+            MashupLayout::TwoLeftOneRight => &[Quadrant, Quadrant, HalfVertical],
+            MashupLayout::OneTopTwoBottom => &[HalfHorizontal, Quadrant, Quadrant],
+            MashupLayout::TwoTopOneBottom => &[Quadrant, Quadrant, HalfHorizontal],
+            MashupLayout::Quadrants => &[Quadrant, Quadrant, Quadrant, Quadrant],
+        }
+    }
+}
+
+impl fmt::Display for MashupLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for MashupLayout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MashupLayout::ALL
+            .into_iter()
+            .find(|layout| layout.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown mashup layout {:?}", s))
+    }
+}
+
+/// Several plugins arranged on a single screen
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Mashup {
+    pub id: i64,
+    pub name: String,
+    pub layout: String,
+    pub current_image: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct MashupPlugin {
+    pub id: i64,
+    pub mashup_id: i64,
+    pub plugin_id: i64,
+    pub position: i32,
+}
+
+/// User editable mashup settings
+#[derive(Debug, Clone)]
+pub struct MashupParams {
+    pub name: String,
+    pub layout: MashupLayout,
+    /// One plugin per slot of the layout
+    pub plugin_ids: Vec<i64>,
+}
+
+impl MashupParams {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("name is required".to_string());
+        }
+        let slots = self.layout.views().len();
+        if self.plugin_ids.len() != slots {
+            return Err(format!(
+                "the {} layout needs {} plugins, got {}",
+                self.layout,
+                slots,
+                self.plugin_ids.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl MashupPlugin {
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<MashupPlugin>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM mashup_plugins ORDER BY id")
+            .fetch_all(pool)
+            .await
+    }
+}
+
+impl Mashup {
+    pub fn layout(&self) -> Result<MashupLayout, String> {
+        self.layout.parse()
+    }
+
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<Mashup>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM mashups ORDER BY name")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<Mashup>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM mashups WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Create a mashup and its slots in one transaction. Callers validate
+    /// `params` first.
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        params: &MashupParams,
+    ) -> Result<Mashup, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let mashup: Mashup =
+            sqlx::query_as("INSERT INTO mashups (name, layout) VALUES (?, ?) RETURNING *")
+                .bind(params.name.trim())
+                .bind(params.layout.name())
+                .fetch_one(&mut *tx)
+                .await?;
+        for (position, plugin_id) in params.plugin_ids.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO mashup_plugins (mashup_id, plugin_id, position) VALUES (?, ?, ?)",
+            )
+            .bind(mashup.id)
+            .bind(plugin_id)
+            .bind(position as i64)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(mashup)
+    }
+
+    /// The plugins in slot order
+    pub async fn plugins(&self, pool: &sqlx::SqlitePool) -> Result<Vec<Plugin>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT plugins.* FROM mashup_plugins JOIN plugins ON plugins.id = mashup_plugins.plugin_id WHERE mashup_id = ? ORDER BY position",
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn set_current_image(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        image: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE mashups SET current_image = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(image)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Forget the current image of every mashup showing the plugin, so they
+    /// are rendered again with its new screen when next shown
+    pub async fn clear_images_showing(
+        pool: &sqlx::SqlitePool,
+        plugin_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE mashups SET current_image = NULL, updated_at = CURRENT_TIMESTAMP WHERE current_image IS NOT NULL AND id IN (SELECT mashup_id FROM mashup_plugins WHERE plugin_id = ?)",
+        )
+        .bind(plugin_id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns whether a mashup was removed
+    pub async fn delete(pool: &sqlx::SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM mashups WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, models::plugin::PluginParams};
+
+    #[test]
+    fn test_layout_names_roundtrip() {
+        for layout in MashupLayout::ALL {
+            assert_eq!(layout.name().parse::<MashupLayout>(), Ok(layout));
+            assert!((2..=4).contains(&layout.views().len()));
+        }
+        assert_eq!("2X2".parse::<MashupLayout>(), Ok(MashupLayout::Quadrants));
+        assert!("3x3".parse::<MashupLayout>().is_err());
+    }
+
+    #[test]
+    fn test_params_need_one_plugin_per_slot() {
+        let mut params = MashupParams {
+            name: "Morning".to_string(),
+            layout: MashupLayout::OneLeftTwoRight,
+            plugin_ids: vec![1, 2, 3],
+        };
+        assert!(params.validate().is_ok());
+        params.plugin_ids.pop();
+        assert!(params.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plugins_in_slot_order() {
+        let pool = db::test_pool().await;
+        let mut ids = Vec::new();
+        for name in ["Weather", "Calendar"] {
+            let plugin = Plugin::create(
+                &pool,
+                &PluginParams {
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            ids.push(plugin.id);
+        }
+        ids.reverse();
+
+        let mashup = Mashup::create(
+            &pool,
+            &MashupParams {
+                name: "Desk".to_string(),
+                layout: MashupLayout::OneTopOneBottom,
+                plugin_ids: ids,
+            },
+        )
+        .await
+        .unwrap();
+        let names: Vec<String> = mashup
+            .plugins(&pool)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, vec!["Calendar", "Weather"]);
+        assert_eq!(mashup.layout(), Ok(MashupLayout::OneTopOneBottom));
+    }
+}
@@ -1,2 +1,7 @@
 pub mod device;
+pub mod mashup;
+pub mod partial;
+pub mod playlist;
+pub mod plugin;
+pub mod screen;
 pub mod state;
@@ -0,0 +1,105 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::*;
+
+/// Liquid markup shared between plugins through `{% render %}` and `{% include %}`
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Partial {
+    pub id: i64,
+    pub name: String,
+    pub markup: String,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// User editable partial settings
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PartialParams {
+    pub name: String,
+    pub markup: String,
+}
+
+impl Partial {
+    /// Partial names are used as `{% render "name" %}`, so keep them to
+    /// characters that need no quoting thought: letters, digits, `_`, `-` and `/`
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '/'))
+    }
+
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<Partial>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM partials ORDER BY name")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<Partial>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM partials WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        params: &PartialParams,
+    ) -> Result<Partial, sqlx::Error> {
+        sqlx::query_as("INSERT INTO partials (name, markup) VALUES (?, ?) RETURNING *")
+            .bind(&params.name)
+            .bind(&params.markup)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn update(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        params: &PartialParams,
+    ) -> Result<Option<Partial>, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE partials SET name = ?, markup = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ? RETURNING *",
+        )
+        .bind(&params.name)
+        .bind(&params.markup)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Returns whether a partial was removed
+    pub async fn delete(pool: &sqlx::SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM partials WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[test]
+    fn test_valid_name() {
+        assert!(Partial::valid_name("title_bar"));
+        assert!(Partial::valid_name("weather/footer-2"));
+        assert!(!Partial::valid_name(""));
+        assert!(!Partial::valid_name("title bar"));
+        assert!(!Partial::valid_name("\"quoted\""));
+    }
+
+    #[tokio::test]
+    async fn test_names_are_unique() {
+        let pool = db::test_pool().await;
+        let params = PartialParams {
+            name: "footer".to_string(),
+            markup: "<footer></footer>".to_string(),
+        };
+        Partial::create(&pool, &params).await.unwrap();
+        assert!(Partial::create(&pool, &params).await.is_err());
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{Datelike, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::*;
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Playlist {
+    pub id: i64,
+    pub device_id: i64,
+    pub name: String,
+    pub is_active: Option<bool>,
+    pub weekdays: Option<String>,
+    pub active_from: Option<String>,
+    pub active_until: Option<String>,
+    pub refresh_time: Option<i32>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct PlaylistItem {
+    pub id: i64,
+    pub playlist_id: i64,
+    /// Set for plugin items; mashup items have `mashup_id` instead
+    pub plugin_id: Option<i64>,
+    pub mashup_id: Option<i64>,
+    pub order_index: Option<i32>,
+    pub is_active: Option<bool>,
+    pub last_displayed_at: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// User editable playlist settings
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct PlaylistParams {
+    pub device_id: i64,
+    pub name: String,
+    pub weekdays: Option<String>,
+    pub active_from: Option<String>,
+    pub active_until: Option<String>,
+    pub refresh_time: Option<i32>,
+}
+
+impl PlaylistItem {
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<PlaylistItem>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM playlist_items ORDER BY id")
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Note that the item just became the device's screen
+    pub async fn mark_displayed(pool: &sqlx::SqlitePool, id: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE playlist_items SET last_displayed_at = strftime('%Y-%m-%d %H:%M:%f', 'now'), updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+}
+
+/// A time of day as entered in the admin, `HH:MM` or `HH:MM:SS`
+fn parse_time(time: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(time.trim(), "%H:%M:%S"))
+        .ok()
+}
+
+impl Playlist {
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<Playlist>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM playlists ORDER BY device_id, id")
+            .fetch_all(pool)
+            .await
+    }
+
+    /// The device's playlists, the first scheduled one of which is shown
+    pub async fn for_device(
+        pool: &sqlx::SqlitePool,
+        device_id: i64,
+    ) -> Result<Vec<Playlist>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM playlists WHERE device_id = ? ORDER BY id")
+            .bind(device_id)
+            .fetch_all(pool)
+            .await
+    }
+
+    /// Whether the playlist is switched on and its weekdays (e.g.
+    /// `mon,tue`) and `active_from`..`active_until` hours include the local
+    /// time `now`. Windows may wrap past midnight; unset or unreadable
+    /// bounds don't restrict anything.
+    pub fn is_scheduled(&self, now: NaiveDateTime) -> bool {
+        if self.is_active == Some(false) {
+            return false;
+        }
+        if let Some(weekdays) = &self.weekdays {
+            let today = now.weekday().to_string().to_lowercase();
+            let listed = weekdays
+                .split(',')
+                .map(|day| day.trim().to_lowercase())
+                .any(|day| day.get(..3).is_some_and(|day| today.starts_with(day)));
+            if !listed {
+                return false;
+            }
+        }
+        let from = self.active_from.as_deref().and_then(parse_time);
+        let until = self.active_until.as_deref().and_then(parse_time);
+        let time = now.time();
+        match (from, until) {
+            (Some(from), Some(until)) if from <= until => from <= time && time < until,
+            (Some(from), Some(until)) => from <= time || time < until,
+            (Some(from), None) => from <= time,
+            (None, Some(until)) => time < until,
+            (None, None) => true,
+        }
+    }
+
+    /// The item to show at `now` (UTC) out of the active `items`: the one on
+    /// screen until `refresh_time` seconds have passed, then the one after
+    /// it. Without a `refresh_time` the playlist moves on at every check-in.
+    pub fn next_item<'a>(
+        &self,
+        items: &'a [PlaylistItem],
+        now: NaiveDateTime,
+    ) -> Option<&'a PlaylistItem> {
+        let items: Vec<&PlaylistItem> = items
+            .iter()
+            .filter(|item| item.is_active != Some(false))
+            .collect();
+        let current = items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| Some((index, item.last_displayed_at?)))
+            .max_by_key(|(_, shown)| *shown);
+        let Some((index, shown)) = current else {
+            return items.first().copied();
+        };
+        let fresh = self
+            .refresh_time
+            .is_some_and(|seconds| (now - shown).num_seconds() < i64::from(seconds));
+        if fresh {
+            Some(items[index])
+        } else {
+            Some(items[(index + 1) % items.len()])
+        }
+    }
+
+    pub async fn find(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<Playlist>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM playlists WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        params: &PlaylistParams,
+    ) -> Result<Playlist, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO playlists (device_id, name, weekdays, active_from, active_until, refresh_time) VALUES (?, ?, ?, ?, ?, ?) RETURNING *"
+        )
+        .bind(params.device_id)
+        .bind(&params.name)
+        .bind(&params.weekdays)
+        .bind(&params.active_from)
+        .bind(&params.active_until)
+        .bind(params.refresh_time)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Returns whether a playlist was removed
+    pub async fn delete(pool: &sqlx::SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM playlists WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn items(&self, pool: &sqlx::SqlitePool) -> Result<Vec<PlaylistItem>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT * FROM playlist_items WHERE playlist_id = ? ORDER BY order_index, id",
+        )
+        .bind(self.id)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Append a plugin to the end of the playlist
+    pub async fn add_item(
+        &self,
+        pool: &sqlx::SqlitePool,
+        plugin_id: i64,
+    ) -> Result<PlaylistItem, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO playlist_items (playlist_id, plugin_id, order_index) VALUES (?, ?, (SELECT COALESCE(MAX(order_index), -1) + 1 FROM playlist_items WHERE playlist_id = ?)) RETURNING *"
+        )
+        .bind(self.id)
+        .bind(plugin_id)
+        .bind(self.id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Append a mashup to the end of the playlist
+    pub async fn add_mashup_item(
+        &self,
+        pool: &sqlx::SqlitePool,
+        mashup_id: i64,
+    ) -> Result<PlaylistItem, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO playlist_items (playlist_id, mashup_id, order_index) VALUES (?, ?, (SELECT COALESCE(MAX(order_index), -1) + 1 FROM playlist_items WHERE playlist_id = ?)) RETURNING *"
+        )
+        .bind(self.id)
+        .bind(mashup_id)
+        .bind(self.id)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Returns whether an item was removed
+    pub async fn remove_item(
+        &self,
+        pool: &sqlx::SqlitePool,
+        item_id: i64,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM playlist_items WHERE id = ? AND playlist_id = ?")
+            .bind(item_id)
+            .bind(self.id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(datetime: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn playlist() -> Playlist {
+        Playlist {
+            id: 1,
+            device_id: 1,
+            name: "Kitchen".to_string(),
+            is_active: None,
+            weekdays: None,
+            active_from: None,
+            active_until: None,
+            refresh_time: None,
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    fn item(id: i64, last_displayed_at: Option<&str>) -> PlaylistItem {
+        PlaylistItem {
+            id,
+            playlist_id: 1,
+            plugin_id: Some(id),
+            mashup_id: None,
+            order_index: Some(id as i32),
+            is_active: None,
+            last_displayed_at: last_displayed_at.map(at),
+            created_at: None,
+            updated_at: None,
+        }
+    }
+
+    #[test]
+    fn test_is_scheduled() {
+        // 2025-07-07 is a Monday
+        let monday_morning = at("2025-07-07 08:30:00");
+        let mut playlist = playlist();
+        assert!(playlist.is_scheduled(monday_morning));
+
+        playlist.weekdays = Some("Mon, wed".to_string());
+        assert!(playlist.is_scheduled(monday_morning));
+        assert!(!playlist.is_scheduled(at("2025-07-08 08:30:00")));
+
+        playlist.active_from = Some("08:00".to_string());
+        playlist.active_until = Some("09:00".to_string());
+        assert!(playlist.is_scheduled(monday_morning));
+        assert!(!playlist.is_scheduled(at("2025-07-07 09:00:00")));
+
+        // Overnight
+        playlist.active_from = Some("22:00".to_string());
+        playlist.active_until = Some("06:00:00".to_string());
+        assert!(playlist.is_scheduled(at("2025-07-07 23:00:00")));
+        assert!(playlist.is_scheduled(at("2025-07-07 05:00:00")));
+        assert!(!playlist.is_scheduled(monday_morning));
+
+        playlist.active_from = None;
+        playlist.active_until = None;
+        playlist.is_active = Some(false);
+        assert!(!playlist.is_scheduled(monday_morning));
+    }
+
+    #[test]
+    fn test_next_item_rotates() {
+        let now = at("2025-07-07 08:30:00");
+        let mut playlist = playlist();
+        assert!(playlist.next_item(&[], now).is_none());
+
+        let items = [item(1, None), item(2, None), item(3, None)];
+        assert_eq!(playlist.next_item(&items, now).unwrap().id, 1);
+
+        let items = [
+            item(1, Some("2025-07-07 08:00:00")),
+            item(2, Some("2025-07-07 08:29:00")),
+            item(3, None),
+        ];
+        assert_eq!(playlist.next_item(&items, now).unwrap().id, 3);
+
+        // Still within its refresh time
+        playlist.refresh_time = Some(300);
+        assert_eq!(playlist.next_item(&items, now).unwrap().id, 2);
+
+        // Wraps around, skipping disabled items
+        let mut items = [
+            item(1, Some("2025-07-07 08:00:00")),
+            item(2, Some("2025-07-07 08:10:00")),
+            item(3, Some("2025-07-07 08:20:00")),
+        ];
+        items[0].is_active = Some(false);
+        assert_eq!(playlist.next_item(&items, now).unwrap().id, 2);
+    }
+}
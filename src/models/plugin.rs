@@ -0,0 +1,220 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use sqlx::prelude::*;
+
+#[derive(FromRow, Serialize, Deserialize, Clone, Debug)]
+pub struct Plugin {
+    pub id: i64,
+    pub uuid: String,
+    pub name: String,
+    pub data_payload: Option<String>,
+    pub data_stale_minutes: Option<i32>,
+    pub data_strategy: Option<String>,
+    pub polling_url: Option<String>,
+    pub polling_verb: Option<String>,
+    pub polling_header: Option<String>,
+    pub render_markup: Option<String>,
+    pub render_markup_view: Option<String>,
+    pub flux_icon_name: Option<String>,
+    pub is_native: Option<bool>,
+    pub data_payload_updated_at: Option<NaiveDateTime>,
+    pub current_image: Option<String>,
+    pub mqtt_topics: Option<String>,
+    pub layout: Option<String>,
+    pub markup_half_horizontal: Option<String>,
+    pub markup_half_vertical: Option<String>,
+    pub markup_quadrant: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// User editable plugin settings
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PluginParams {
+    pub name: String,
+    pub data_strategy: Option<String>,
+    pub data_payload: Option<String>,
+    pub data_stale_minutes: Option<i32>,
+    pub polling_url: Option<String>,
+    pub polling_verb: Option<String>,
+    pub polling_header: Option<String>,
+    pub render_markup: Option<String>,
+    pub mqtt_topics: Option<String>,
+    pub layout: Option<String>,
+    pub markup_half_horizontal: Option<String>,
+    pub markup_half_vertical: Option<String>,
+    pub markup_quadrant: Option<String>,
+}
+
+/// The TRMNL view sizes a plugin can be drawn at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    Full,
+    HalfHorizontal,
+    HalfVertical,
+    Quadrant,
+}
+
+impl View {
+    /// CSS classes of the framework's view container
+    pub fn class(&self) -> &'static str {
+        match self {
+            View::Full => "view view--full",
+            View::HalfHorizontal => "view view--half_horizontal",
+            View::HalfVertical => "view view--half_vertical",
+            View::Quadrant => "view view--quadrant",
+        }
+    }
+}
+
+impl Plugin {
+    /// Markup for a view size, falling back to the full view markup for
+    /// sizes the plugin doesn't customise
+    pub fn markup_for(&self, view: View) -> &str {
+        let markup = match view {
+            View::Full => None,
+            View::HalfHorizontal => self.markup_half_horizontal.as_deref(),
+            View::HalfVertical => self.markup_half_vertical.as_deref(),
+            View::Quadrant => self.markup_quadrant.as_deref(),
+        };
+        markup
+            .filter(|m| !m.trim().is_empty())
+            .or(self.render_markup.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Topic filters for MQTT backed plugins, one per line or comma separated
+    pub fn topics(&self) -> Vec<String> {
+        self.mqtt_topics
+            .as_deref()
+            .unwrap_or_default()
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub async fn with_strategy(
+        pool: &sqlx::SqlitePool,
+        strategy: &str,
+    ) -> Result<Vec<Plugin>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM plugins WHERE data_strategy = ? ORDER BY id")
+            .bind(strategy)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn all(pool: &sqlx::SqlitePool) -> Result<Vec<Plugin>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM plugins ORDER BY name")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find(pool: &sqlx::SqlitePool, id: i64) -> Result<Option<Plugin>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM plugins WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn find_by_uuid(
+        pool: &sqlx::SqlitePool,
+        uuid: &str,
+    ) -> Result<Option<Plugin>, sqlx::Error> {
+        sqlx::query_as("SELECT * FROM plugins WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn create(
+        pool: &sqlx::SqlitePool,
+        params: &PluginParams,
+    ) -> Result<Plugin, sqlx::Error> {
+        sqlx::query_as(
+            "INSERT INTO plugins (uuid, name, data_strategy, data_payload, data_stale_minutes, polling_url, polling_verb, polling_header, render_markup, mqtt_topics, layout, markup_half_horizontal, markup_half_vertical, markup_quadrant) VALUES (?, ?, ?, ?, ?, ?, COALESCE(?, 'GET'), ?, ?, ?, ?, ?, ?, ?) RETURNING *"
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(&params.name)
+        .bind(&params.data_strategy)
+        .bind(&params.data_payload)
+        .bind(params.data_stale_minutes)
+        .bind(&params.polling_url)
+        .bind(&params.polling_verb)
+        .bind(&params.polling_header)
+        .bind(&params.render_markup)
+        .bind(&params.mqtt_topics)
+        .bind(&params.layout)
+        .bind(&params.markup_half_horizontal)
+        .bind(&params.markup_half_vertical)
+        .bind(&params.markup_quadrant)
+        .fetch_one(pool)
+        .await
+    }
+
+    /// Save new settings. The current image was rendered from the old ones,
+    /// so it is dropped and the plugin renders afresh when next shown.
+    pub async fn update(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        params: &PluginParams,
+    ) -> Result<Option<Plugin>, sqlx::Error> {
+        sqlx::query_as(
+            "UPDATE plugins SET name = ?, data_strategy = ?, data_payload = ?, data_stale_minutes = ?, polling_url = ?, polling_verb = COALESCE(?, 'GET'), polling_header = ?, render_markup = ?, mqtt_topics = ?, layout = ?, markup_half_horizontal = ?, markup_half_vertical = ?, markup_quadrant = ?, current_image = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = ? RETURNING *"
+        )
+        .bind(&params.name)
+        .bind(&params.data_strategy)
+        .bind(&params.data_payload)
+        .bind(params.data_stale_minutes)
+        .bind(&params.polling_url)
+        .bind(&params.polling_verb)
+        .bind(&params.polling_header)
+        .bind(&params.render_markup)
+        .bind(&params.mqtt_topics)
+        .bind(&params.layout)
+        .bind(&params.markup_half_horizontal)
+        .bind(&params.markup_half_vertical)
+        .bind(&params.markup_quadrant)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn update_data_payload(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        payload: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE plugins SET data_payload = ?, data_payload_updated_at = CURRENT_TIMESTAMP WHERE id = ?")
+            .bind(payload)
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_current_image(
+        pool: &sqlx::SqlitePool,
+        id: i64,
+        image: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE plugins SET current_image = ?, updated_at = CURRENT_TIMESTAMP WHERE id = ?",
+        )
+        .bind(image)
+        .bind(id)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns whether a plugin was removed
+    pub async fn delete(pool: &sqlx::SqlitePool, id: i64) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM plugins WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -0,0 +1,181 @@
+use chrono::NaiveDateTime;
+use serde::Serialize;
+use sqlx::prelude::*;
+
+/// Entries kept per device; older ones are dropped as new screens are shown
+const HISTORY_PER_DEVICE: i64 = 1000;
+
+/// A screen served to a device by `/api/display`, from its first check-in
+/// receiving it to the last one
+#[derive(FromRow, Serialize, Clone, Debug)]
+pub struct ScreenRecord {
+    pub id: i64,
+    pub device_id: i64,
+    pub filename: String,
+    pub plugin_id: Option<i64>,
+    pub mashup_id: Option<i64>,
+    pub source: Option<String>,
+    pub shown_at: NaiveDateTime,
+    pub last_shown_at: NaiveDateTime,
+}
+
+impl ScreenRecord {
+    /// Note that `filename` was just sent to a device. Repeats of the
+    /// latest screen extend its entry instead of adding one.
+    pub async fn record(
+        pool: &sqlx::SqlitePool,
+        device_id: i64,
+        filename: &str,
+    ) -> Result<ScreenRecord, sqlx::Error> {
+        let extended = sqlx::query_as(
+            "UPDATE screen_history SET last_shown_at = CURRENT_TIMESTAMP
+             WHERE id = (SELECT id FROM screen_history WHERE device_id = ? ORDER BY id DESC LIMIT 1)
+             AND filename = ? RETURNING *",
+        )
+        .bind(device_id)
+        .bind(filename)
+        .fetch_optional(pool)
+        .await?;
+        if let Some(record) = extended {
+            return Ok(record);
+        }
+
+        // Rendered screens are named `<image>.<format>`, as stored in the
+        // plugin's or mashup's current_image
+        let image = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+        let record = sqlx::query_as(
+            "INSERT INTO screen_history (device_id, filename, plugin_id, mashup_id, source)
+             SELECT ?, ?, plugin.id, mashup.id, COALESCE(plugin.name, mashup.name)
+             FROM (SELECT 1)
+             LEFT JOIN (SELECT id, name FROM plugins WHERE current_image = ? LIMIT 1) AS plugin
+             LEFT JOIN (SELECT id, name FROM mashups WHERE current_image = ? LIMIT 1) AS mashup
+             RETURNING *",
+        )
+        .bind(device_id)
+        .bind(filename)
+        .bind(image)
+        .bind(image)
+        .fetch_one(pool)
+        .await?;
+        sqlx::query(
+            "DELETE FROM screen_history WHERE device_id = ? AND id NOT IN
+             (SELECT id FROM screen_history WHERE device_id = ? ORDER BY id DESC LIMIT ?)",
+        )
+        .bind(device_id)
+        .bind(device_id)
+        .bind(HISTORY_PER_DEVICE)
+        .execute(pool)
+        .await?;
+        Ok(record)
+    }
+
+    /// The device's most recent screens first, only those first shown at or
+    /// before `before` when given
+    pub async fn for_device(
+        pool: &sqlx::SqlitePool,
+        device_id: i64,
+        before: Option<NaiveDateTime>,
+        limit: i64,
+    ) -> Result<Vec<ScreenRecord>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT * FROM screen_history WHERE device_id = ? AND (? IS NULL OR shown_at <= ?)
+             ORDER BY shown_at DESC, id DESC LIMIT ?",
+        )
+        .bind(device_id)
+        .bind(before)
+        .bind(before)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RenderConfig,
+        db,
+        models::{
+            device::{Device, MacAddress},
+            plugin::{Plugin, PluginParams},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_record_history() {
+        let pool = db::test_pool().await;
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "Kitchen",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Weather".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        Plugin::set_current_image(&pool, plugin.id, "abc")
+            .await
+            .unwrap();
+
+        let setup = ScreenRecord::record(&pool, device.id, "setup-logo.bmp")
+            .await
+            .unwrap();
+        assert_eq!((setup.plugin_id, setup.source), (None, None));
+        let weather = ScreenRecord::record(&pool, device.id, "abc.png")
+            .await
+            .unwrap();
+        assert_eq!(weather.plugin_id, Some(plugin.id));
+        assert_eq!(weather.source.as_deref(), Some("Weather"));
+        let again = ScreenRecord::record(&pool, device.id, "abc.png")
+            .await
+            .unwrap();
+        assert_eq!(again.id, weather.id);
+
+        let history = ScreenRecord::for_device(&pool, device.id, None, 10)
+            .await
+            .unwrap();
+        let files: Vec<&str> = history.iter().map(|r| r.filename.as_str()).collect();
+        assert_eq!(files, ["abc.png", "setup-logo.bmp"]);
+
+        sqlx::query("UPDATE screen_history SET shown_at = '2025-07-01 09:00:00' WHERE id = ?")
+            .bind(weather.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE screen_history SET shown_at = '2025-07-01 07:00:00' WHERE id = ?")
+            .bind(setup.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let at_eight =
+            NaiveDateTime::parse_from_str("2025-07-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        let history = ScreenRecord::for_device(&pool, device.id, Some(at_eight), 1)
+            .await
+            .unwrap();
+        assert_eq!(history[0].filename, "setup-logo.bmp");
+
+        // Deleting the plugin keeps the entry and its name
+        sqlx::query("DELETE FROM plugins WHERE id = ?")
+            .bind(plugin.id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let history = ScreenRecord::for_device(&pool, device.id, None, 10)
+            .await
+            .unwrap();
+        assert_eq!(history[0].plugin_id, None);
+        assert_eq!(history[0].source.as_deref(), Some("Weather"));
+    }
+}
@@ -1,7 +1,50 @@
+use std::sync::Arc;
+
 use sqlx::SqlitePool;
 
+use crate::{
+    auth::AdminAuth,
+    config::Config,
+    mqtt::publish::EventPublisher,
+    render::cache::TemplateCache,
+    storage::{Storage, signing::UrlSigner},
+};
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: SqlitePool,
-    pub base_url: String,
+    pub config: Arc<Config>,
+    pub http: reqwest::Client,
+    /// Set when device events should be published to MQTT
+    pub events: Option<EventPublisher>,
+    /// Parsed plugin markup and layouts, shared with the MQTT subscriber
+    pub templates: TemplateCache,
+    /// Where rendered screens are kept
+    pub storage: Storage,
+    /// Signs the links to rendered screens handed to devices and the admin
+    pub signer: UrlSigner,
+    /// Guards the admin dashboard and the device management API
+    pub admin: AdminAuth,
+}
+
+#[cfg(test)]
+impl AppState {
+    pub fn for_tests(db: SqlitePool) -> Self {
+        let config = Config::for_tests();
+        AppState {
+            db,
+            templates: TemplateCache::new(&config.paths.templates_dir, config.dev_mode),
+            storage: Arc::new(
+                crate::storage::local::LocalStorage::new(
+                    std::env::temp_dir().join(format!("patina-images-{}", uuid::Uuid::new_v4())),
+                )
+                .unwrap(),
+            ),
+            signer: UrlSigner::new(b"test signing key"),
+            admin: AdminAuth::new("test admin password"),
+            config: Arc::new(config),
+            http: reqwest::Client::new(),
+            events: None,
+        }
+    }
 }
@@ -0,0 +1,329 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{debug, warn};
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::models::device::Device;
+
+/// Publishes device activity to MQTT, announcing every device to Home
+/// Assistant through its discovery protocol the first time it is seen.
+#[derive(Clone)]
+pub struct EventPublisher {
+    client: AsyncClient,
+    topic_prefix: String,
+    discovery_prefix: String,
+    announced: Arc<Mutex<HashSet<i64>>>,
+    screens: Arc<Mutex<HashMap<i64, String>>>,
+}
+
+/// A Home Assistant sensor read from a key of the device state message
+struct Sensor {
+    object_id: &'static str,
+    name: &'static str,
+    device_class: Option<&'static str>,
+    unit: Option<&'static str>,
+}
+
+const SENSORS: [Sensor; 5] = [
+    Sensor {
+        object_id: "battery_voltage",
+        name: "Battery voltage",
+        device_class: Some("voltage"),
+        unit: Some("V"),
+    },
+    Sensor {
+        object_id: "rssi",
+        name: "Signal strength",
+        device_class: Some("signal_strength"),
+        unit: Some("dBm"),
+    },
+    Sensor {
+        object_id: "last_seen",
+        name: "Last seen",
+        device_class: Some("timestamp"),
+        unit: None,
+    },
+    Sensor {
+        object_id: "screen",
+        name: "Screen",
+        device_class: None,
+        unit: None,
+    },
+    Sensor {
+        object_id: "firmware_version",
+        name: "Firmware version",
+        device_class: None,
+        unit: None,
+    },
+];
+
+impl EventPublisher {
+    pub fn new(client: AsyncClient, topic_prefix: &str, discovery_prefix: &str) -> Self {
+        EventPublisher {
+            client,
+            topic_prefix: topic_prefix.to_string(),
+            discovery_prefix: discovery_prefix.to_string(),
+            announced: Arc::new(Mutex::new(HashSet::new())),
+            screens: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Connect to the broker and keep the connection serviced in the background
+    pub fn connect(options: MqttOptions, topic_prefix: &str, discovery_prefix: &str) -> Self {
+        let (client, mut eventloop) = AsyncClient::new(options, 256);
+        tokio::spawn(async move {
+            loop {
+                if let Err(err) = eventloop.poll().await {
+                    warn!("MQTT publisher connection error: {}", err);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            }
+        });
+        Self::new(client, topic_prefix, discovery_prefix)
+    }
+
+    /// Identifier used for a device in topics and Home Assistant unique ids
+    pub fn node_id(device: &Device) -> String {
+        format!(
+            "trmnl_{}",
+            device.mac_address.replace(':', "").to_lowercase()
+        )
+    }
+
+    fn device_topic(&self, device: &Device, suffix: &str) -> String {
+        format!("{}/{}/{}", self.topic_prefix, Self::node_id(device), suffix)
+    }
+
+    /// Retained Home Assistant discovery configs for every sensor of a device
+    pub fn discovery_messages(&self, device: &Device) -> Vec<(String, Value)> {
+        let node_id = Self::node_id(device);
+        let device_info = json!({
+            "identifiers": [node_id],
+            "connections": [["mac", device.mac_address.to_lowercase()]],
+            "name": device.name.clone().or(device.friendly_id.clone()),
+            "manufacturer": "TRMNL",
+            "model": device.model,
+            "sw_version": device.last_firmware_version,
+        });
+        SENSORS
+            .iter()
+            .map(|sensor| {
+                let object_id = sensor.object_id;
+                let mut config = json!({
+                    "name": sensor.name,
+                    "unique_id": format!("{}_{}", node_id, object_id),
+                    "object_id": format!("{}_{}", node_id, object_id),
+                    "state_topic": self.device_topic(device, "state"),
+                    "value_template": format!("{{{{ value_json.{} }}}}", object_id),
+                    "device": device_info,
+                });
+                if let Some(device_class) = sensor.device_class {
+                    config["device_class"] = json!(device_class);
+                }
+                if let Some(unit) = sensor.unit {
+                    config["unit_of_measurement"] = json!(unit);
+                    config["state_class"] = json!("measurement");
+                }
+                let topic = format!(
+                    "{}/sensor/{}/{}/config",
+                    self.discovery_prefix, node_id, object_id
+                );
+                (topic, config)
+            })
+            .collect()
+    }
+
+    /// Current device state, read by every discovered sensor
+    pub fn state_message(&self, device: &Device) -> (String, Value) {
+        let screen = self.screens.lock().unwrap().get(&device.id).cloned();
+        let last_seen = device.last_seen_at.map(|seen| seen.and_utc().to_rfc3339());
+        let state = json!({
+            "battery_voltage": device.last_battery_voltage,
+            "rssi": device.last_rssi_level,
+            "firmware_version": device.last_firmware_version,
+            "last_seen": last_seen,
+            "screen": screen,
+        });
+        (self.device_topic(device, "state"), state)
+    }
+
+    /// Announce the device if needed and publish its check-in telemetry
+    pub fn check_in(&self, device: &Device) {
+        let first_seen = self.announced.lock().unwrap().insert(device.id);
+        if first_seen {
+            for (topic, config) in self.discovery_messages(device) {
+                self.send(topic, &config, true);
+            }
+        }
+        let (topic, state) = self.state_message(device);
+        self.send(topic, &state, true);
+    }
+
+    /// Publish a log line reported by the device
+    pub fn log<T: Serialize>(&self, device: &Device, log: &T) {
+        self.send(self.device_topic(device, "log"), log, false);
+    }
+
+    /// Publish the image a device was sent, but only when it changed
+    pub fn screen(&self, device: &Device, filename: &str) {
+        let previous = self
+            .screens
+            .lock()
+            .unwrap()
+            .insert(device.id, filename.to_string());
+        if previous.as_deref() == Some(filename) {
+            return;
+        }
+        self.send(
+            self.device_topic(device, "screen"),
+            &json!({ "filename": filename }),
+            true,
+        );
+        let (topic, state) = self.state_message(device);
+        self.send(topic, &state, true);
+    }
+
+    /// Queue a message without waiting; events are dropped while the broker is
+    /// unreachable rather than holding up the device.
+    fn send<T: Serialize + ?Sized>(&self, topic: String, payload: &T, retain: bool) {
+        let payload = match serde_json::to_vec(payload) {
+            Ok(payload) => payload,
+            Err(err) => return warn!("failed to encode MQTT payload for {}: {}", topic, err),
+        };
+        match self
+            .client
+            .try_publish(topic.as_str(), QoS::AtLeastOnce, retain, payload)
+        {
+            Ok(()) => debug!("published to {}", topic),
+            Err(err) => warn!("dropped MQTT event for {}: {}", topic, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+    use rumqttc::Request;
+
+    fn test_device() -> Device {
+        let time =
+            NaiveDateTime::parse_from_str("2025-06-01 08:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+        Device {
+            id: 7,
+            name: Some("Kitchen".to_string()),
+            mac_address: "AA:BB:CC:DD:EE:FF".to_string(),
+            api_key: "key".to_string(),
+            friendly_id: Some("device-EE:FF".to_string()),
+            proxy_cloud: false,
+            current_screen_image: None,
+            last_battery_voltage: Some(4.02),
+            last_rssi_level: Some(-60),
+            last_firmware_version: Some("1.5.5".to_string()),
+            default_refresh_interval: 60,
+            width: 800,
+            height: 480,
+            rotate: 0,
+            image_format: "png".to_string(),
+            model: Some("og".to_string()),
+            last_refresh_rate: None,
+            last_seen_at: Some(time),
+            cloud_api_key: None,
+            created_at: time,
+            updated_at: time,
+        }
+    }
+
+    fn test_publisher() -> (EventPublisher, flume::Receiver<Request>) {
+        let (tx, rx) = flume::unbounded();
+        let client = AsyncClient::from_senders(tx);
+        (EventPublisher::new(client, "patina", "homeassistant"), rx)
+    }
+
+    fn published(rx: &flume::Receiver<Request>) -> Vec<(String, Value, bool)> {
+        rx.try_iter()
+            .filter_map(|request| match request {
+                Request::Publish(publish) => Some((
+                    publish.topic,
+                    serde_json::from_slice(&publish.payload).unwrap(),
+                    publish.retain,
+                )),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_discovery_messages() {
+        let (publisher, _) = test_publisher();
+        let messages = publisher.discovery_messages(&test_device());
+
+        assert_eq!(messages.len(), SENSORS.len());
+        let (topic, config) = &messages[0];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/trmnl_aabbccddeeff/battery_voltage/config"
+        );
+        assert_eq!(config["unique_id"], "trmnl_aabbccddeeff_battery_voltage");
+        assert_eq!(config["state_topic"], "patina/trmnl_aabbccddeeff/state");
+        assert_eq!(config["value_template"], "{{ value_json.battery_voltage }}");
+        assert_eq!(config["device_class"], "voltage");
+        assert_eq!(config["unit_of_measurement"], "V");
+        assert_eq!(config["device"]["name"], "Kitchen");
+        assert_eq!(config["device"]["identifiers"][0], "trmnl_aabbccddeeff");
+    }
+
+    #[test]
+    fn test_check_in_announces_once() {
+        let (publisher, rx) = test_publisher();
+        let device = test_device();
+
+        publisher.check_in(&device);
+        let first = published(&rx);
+        assert_eq!(first.len(), SENSORS.len() + 1);
+        let (topic, state, retain) = first.last().unwrap();
+        assert_eq!(topic, "patina/trmnl_aabbccddeeff/state");
+        assert_eq!(state["battery_voltage"], 4.02);
+        assert_eq!(state["rssi"], -60);
+        assert_eq!(state["last_seen"], "2025-06-01T08:00:00+00:00");
+        assert!(retain);
+
+        publisher.check_in(&device);
+        assert_eq!(published(&rx).len(), 1);
+    }
+
+    #[test]
+    fn test_screen_only_published_on_change() {
+        let (publisher, rx) = test_publisher();
+        let device = test_device();
+
+        publisher.screen(&device, "a.png");
+        let messages = published(&rx);
+        assert_eq!(messages[0].0, "patina/trmnl_aabbccddeeff/screen");
+        assert_eq!(messages[0].1["filename"], "a.png");
+        assert_eq!(messages[1].1["screen"], "a.png");
+
+        publisher.screen(&device, "a.png");
+        assert!(published(&rx).is_empty());
+        publisher.screen(&device, "b.png");
+        assert_eq!(published(&rx).len(), 2);
+    }
+
+    #[test]
+    fn test_log_is_not_retained() {
+        let (publisher, rx) = test_publisher();
+
+        publisher.log(&test_device(), &json!({ "log_message": "woke up" }));
+        let messages = published(&rx);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, "patina/trmnl_aabbccddeeff/log");
+        assert_eq!(messages[0].1["log_message"], "woke up");
+        assert!(!messages[0].2);
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    collections::{HashMap, HashSet},
+    future::Future,
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use serde_json::Value;
+use sqlx::SqlitePool;
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    time::{Instant, sleep_until},
+};
+
+use crate::{
+    config::Config,
+    metrics::METRICS,
+    models::plugin::Plugin,
+    mqtt::topic_matches,
+    render::{cache::TemplateCache, plugin::render_plugin},
+    storage::Storage,
+};
+
+pub const MQTT_STRATEGY: &str = "mqtt";
+
+/// Quiet period after the last message before a plugin is re-rendered
+const DEBOUNCE: Duration = Duration::from_secs(5);
+/// How often newly configured topics are picked up
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// Subscribe to the topics of every MQTT backed plugin, merge incoming
+/// messages into their data payload and re-render them once things settle.
+pub async fn run(
+    pool: SqlitePool,
+    config: Arc<Config>,
+    templates: TemplateCache,
+    storage: Storage,
+    options: MqttOptions,
+) {
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let render_pool = pool.clone();
+    tokio::spawn(debounce(rx, DEBOUNCE, move |id| {
+        let pool = render_pool.clone();
+        let config = config.clone();
+        let templates = templates.clone();
+        let storage = storage.clone();
+        async move {
+            let plugin = match Plugin::find(&pool, id).await {
+                Ok(Some(plugin)) => plugin,
+                Ok(None) => return,
+                Err(err) => return warn!("failed to load plugin {}: {}", id, err),
+            };
+            match render_plugin(&pool, &config, &templates, storage.as_ref(), &plugin).await {
+                Ok(image) => info!("re-rendered plugin {} as {}", plugin.uuid, image.id()),
+                Err(err) => warn!("failed to render plugin {}: {}", plugin.uuid, err),
+            }
+        }
+    }));
+
+    let mut subscribed = HashSet::new();
+    let mut refresh = tokio::time::interval(REFRESH);
+    loop {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("connected to MQTT broker");
+                    // Subscriptions do not survive a clean reconnect
+                    subscribed.clear();
+                    subscribe_all(&pool, &client, &mut subscribed).await;
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let handled = handle_message(&pool, &publish.topic, &publish.payload).await;
+                    METRICS.record_poll("mqtt", handled.is_ok());
+                    match handled {
+                        Ok(changed) => {
+                            for id in changed {
+                                let _ = tx.send(id);
+                            }
+                        }
+                        Err(err) => warn!("failed to store MQTT message on {}: {}", publish.topic, err),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("MQTT connection error: {}", err);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                }
+            },
+            _ = refresh.tick() => subscribe_all(&pool, &client, &mut subscribed).await,
+        }
+    }
+}
+
+async fn subscribe_all(pool: &SqlitePool, client: &AsyncClient, subscribed: &mut HashSet<String>) {
+    let plugins = match Plugin::with_strategy(pool, MQTT_STRATEGY).await {
+        Ok(plugins) => plugins,
+        Err(err) => return warn!("failed to load MQTT plugins: {}", err),
+    };
+    for topic in plugins.iter().flat_map(Plugin::topics) {
+        if subscribed.contains(&topic) {
+            continue;
+        }
+        match client.subscribe(topic.as_str(), QoS::AtLeastOnce).await {
+            Ok(()) => {
+                debug!("subscribed to {}", topic);
+                subscribed.insert(topic);
+            }
+            Err(err) => warn!("failed to subscribe to {}: {}", topic, err),
+        }
+    }
+}
+
+/// Merge a message into every plugin subscribed to its topic, returning the
+/// ids of plugins whose payload actually changed.
+pub async fn handle_message(
+    pool: &SqlitePool,
+    topic: &str,
+    payload: &[u8],
+) -> Result<Vec<i64>, anyhow::Error> {
+    let mut changed = Vec::new();
+    for plugin in Plugin::with_strategy(pool, MQTT_STRATEGY).await? {
+        if !plugin.topics().iter().any(|f| topic_matches(f, topic)) {
+            continue;
+        }
+        let merged = merge_payload(plugin.data_payload.as_deref(), topic, payload);
+        if plugin.data_payload.as_deref() != Some(merged.as_str()) {
+            Plugin::update_data_payload(pool, plugin.id, &merged).await?;
+            changed.push(plugin.id);
+        }
+    }
+    Ok(changed)
+}
+
+/// JSON objects are merged key by key into the existing payload. Anything
+/// else is stored under the topic name with `/` replaced by `_`, so
+/// `home/kitchen/temp` becomes `{{ home_kitchen_temp }}` in templates.
+pub fn merge_payload(existing: Option<&str>, topic: &str, payload: &[u8]) -> String {
+    let mut data = existing
+        .and_then(|e| serde_json::from_str::<Value>(e).ok())
+        .and_then(|v| match v {
+            Value::Object(map) => Some(map),
+            _ => None,
+        })
+        .unwrap_or_default();
+
+    match serde_json::from_slice::<Value>(payload) {
+        Ok(Value::Object(map)) => data.extend(map),
+        Ok(value) => {
+            data.insert(topic.replace('/', "_"), value);
+        }
+        Err(_) => {
+            let text = String::from_utf8_lossy(payload).trim().to_string();
+            data.insert(topic.replace('/', "_"), Value::String(text));
+        }
+    }
+    Value::Object(data).to_string()
+}
+
+/// Call `render` for each id once no new event for it arrived within `delay`
+pub async fn debounce<F, Fut>(mut rx: UnboundedReceiver<i64>, delay: Duration, mut render: F)
+where
+    F: FnMut(i64) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut pending: HashMap<i64, Instant> = HashMap::new();
+    loop {
+        let next = pending.values().min().copied();
+        tokio::select! {
+            id = rx.recv() => match id {
+                Some(id) => {
+                    pending.insert(id, Instant::now() + delay);
+                }
+                None => break,
+            },
+            _ = sleep_until(next.unwrap_or_else(|| Instant::now() + delay)), if next.is_some() => {
+                let now = Instant::now();
+                let due: Vec<i64> = pending
+                    .iter()
+                    .filter(|(_, deadline)| **deadline <= now)
+                    .map(|(id, _)| *id)
+                    .collect();
+                for id in due {
+                    pending.remove(&id);
+                    render(id).await;
+                }
+            }
+        }
+    }
+    // Flush anything still waiting when the sender goes away
+    for id in pending.into_keys() {
+        render(id).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, models::plugin::PluginParams};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_merge_payload_object() {
+        let merged = merge_payload(
+            Some(r#"{"temp": 20, "unit": "C"}"#),
+            "home/kitchen",
+            br#"{"temp": 21.5, "humidity": 40}"#,
+        );
+        let merged: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(merged, json!({"temp": 21.5, "unit": "C", "humidity": 40}));
+    }
+
+    #[test]
+    fn test_merge_payload_scalar_and_text() {
+        let merged = merge_payload(None, "home/kitchen/temp", b"21.5");
+        let merged = merge_payload(Some(&merged), "home/door", b"open");
+        let merged: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(
+            merged,
+            json!({"home_kitchen_temp": 21.5, "home_door": "open"})
+        );
+    }
+
+    #[test]
+    fn test_merge_payload_replaces_invalid_existing() {
+        let merged = merge_payload(Some("not json"), "a", br#"{"b": 1}"#);
+        assert_eq!(merged, r#"{"b":1}"#);
+    }
+
+    #[tokio::test]
+    async fn test_handle_message_updates_matching_plugins() {
+        let pool = db::test_pool().await;
+        let subscribed = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Sensors".to_string(),
+                data_strategy: Some(MQTT_STRATEGY.to_string()),
+                mqtt_topics: Some("home/+/temp, home/door".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let other = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Office".to_string(),
+                data_strategy: Some(MQTT_STRATEGY.to_string()),
+                mqtt_topics: Some("office/#".to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+
+        let changed = handle_message(&pool, "home/kitchen/temp", br#"{"kitchen": 21}"#)
+            .await
+            .unwrap();
+        assert_eq!(changed, vec![subscribed.id]);
+        // The same value again is not a change
+        let changed = handle_message(&pool, "home/kitchen/temp", br#"{"kitchen": 21}"#)
+            .await
+            .unwrap();
+        assert!(changed.is_empty());
+
+        let plugin = Plugin::find(&pool, subscribed.id).await.unwrap().unwrap();
+        assert_eq!(plugin.data_payload.as_deref(), Some(r#"{"kitchen":21}"#));
+        assert!(plugin.data_payload_updated_at.is_some());
+        let other = Plugin::find(&pool, other.id).await.unwrap().unwrap();
+        assert_eq!(other.data_payload, None);
+    }
+
+    #[tokio::test]
+    async fn test_debounce_coalesces_bursts() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let rendered = Arc::new(Mutex::new(Vec::new()));
+        let log = rendered.clone();
+        let task = tokio::spawn(debounce(rx, Duration::from_millis(50), move |id| {
+            let log = log.clone();
+            async move { log.lock().unwrap().push(id) }
+        }));
+
+        for _ in 0..5 {
+            tx.send(1).unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        tx.send(2).unwrap();
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(rendered.lock().unwrap().len(), 2);
+
+        tx.send(3).unwrap();
+        drop(tx);
+        task.await.unwrap();
+        let mut ids = rendered.lock().unwrap().clone();
+        ids.sort();
+        assert_eq!(ids, vec![1, 2, 3]);
+    }
+}
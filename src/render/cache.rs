@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::SystemTime,
+};
+
+use serde_json::json;
+use sqlx::SqlitePool;
+
+use crate::{
+    models::plugin::{Plugin, View},
+    render::{
+        plugin::plugin_data,
+        template::{DEFAULT_LAYOUT, layout_path, load_partials, parser, view_container},
+    },
+};
+
+/// Parsed Liquid templates shared by everything that renders plugins.
+///
+/// The parser carries the partials, so it is built once and dropped with
+/// every parsed template whenever a partial changes. Plugin markup is keyed
+/// by plugin id and a hash of the markup, layouts by name. In dev mode the
+/// templates directory is checked for changes before each use so edits to
+/// layouts and partials show up without a restart.
+#[derive(Clone)]
+pub struct TemplateCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    templates_dir: PathBuf,
+    dev_mode: bool,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    parser: Option<Arc<liquid::Parser>>,
+    plugins: HashMap<(i64, u64), Arc<liquid::Template>>,
+    layouts: HashMap<String, Arc<liquid::Template>>,
+    /// Newest modification time in the templates directory, in dev mode
+    modified: Option<SystemTime>,
+}
+
+impl TemplateCache {
+    pub fn new(templates_dir: impl Into<PathBuf>, dev_mode: bool) -> Self {
+        let templates_dir = templates_dir.into();
+        let state = CacheState {
+            modified: dev_mode
+                .then(|| newest_modification(&templates_dir))
+                .flatten(),
+            ..Default::default()
+        };
+        TemplateCache {
+            inner: Arc::new(Inner {
+                templates_dir,
+                dev_mode,
+                state: Mutex::new(state),
+            }),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, CacheState> {
+        // The state is only ever replaced wholesale, so a panic elsewhere
+        // can't leave it half updated
+        self.inner
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Forget the templates parsed from a plugin's markup, after it was
+    /// updated or removed
+    pub fn invalidate_plugin(&self, plugin_id: i64) {
+        self.state().plugins.retain(|(id, _), _| *id != plugin_id);
+    }
+
+    /// Forget everything, e.g. after a partial changed
+    pub fn clear(&self) {
+        let mut state = self.state();
+        let modified = state.modified;
+        *state = CacheState {
+            modified,
+            ..Default::default()
+        };
+    }
+
+    /// In dev mode, drop everything when a file in the templates directory changed
+    fn reload_if_changed(&self) {
+        if !self.inner.dev_mode {
+            return;
+        }
+        let modified = newest_modification(&self.inner.templates_dir);
+        let mut state = self.state();
+        if state.modified != modified {
+            *state = CacheState {
+                modified,
+                ..Default::default()
+            };
+        }
+    }
+
+    async fn parser(&self, pool: &SqlitePool) -> Result<Arc<liquid::Parser>, anyhow::Error> {
+        self.reload_if_changed();
+        if let Some(parser) = self.state().parser.clone() {
+            return Ok(parser);
+        }
+        let partials = load_partials(pool, &self.inner.templates_dir).await?;
+        let parser = Arc::new(parser(&partials)?);
+        Ok(self.state().parser.get_or_insert(parser).clone())
+    }
+
+    async fn plugin_template(
+        &self,
+        pool: &SqlitePool,
+        plugin_id: i64,
+        markup: &str,
+    ) -> Result<Arc<liquid::Template>, anyhow::Error> {
+        let parser = self.parser(pool).await?;
+        let key = (plugin_id, markup_hash(markup));
+        if let Some(template) = self.state().plugins.get(&key) {
+            return Ok(template.clone());
+        }
+        let template = Arc::new(parser.parse(markup)?);
+        self.state().plugins.insert(key, template.clone());
+        Ok(template)
+    }
+
+    async fn layout_template(
+        &self,
+        pool: &SqlitePool,
+        layout: Option<&str>,
+    ) -> Result<Arc<liquid::Template>, anyhow::Error> {
+        let parser = self.parser(pool).await?;
+        let name = layout.unwrap_or(DEFAULT_LAYOUT);
+        if let Some(template) = self.state().layouts.get(name) {
+            return Ok(template.clone());
+        }
+        let template =
+            Arc::new(parser.parse_file(layout_path(&self.inner.templates_dir, layout)?)?);
+        self.state()
+            .layouts
+            .insert(name.to_string(), template.clone());
+        Ok(template)
+    }
+
+    /// Render a plugin's markup for `view` into the framework's container
+    pub async fn render_view(
+        &self,
+        pool: &SqlitePool,
+        plugin: &Plugin,
+        view: View,
+    ) -> Result<String, anyhow::Error> {
+        let template = self
+            .plugin_template(pool, plugin.id, plugin.markup_for(view))
+            .await?;
+        let content = template.render(&liquid::to_object(&plugin_data(plugin)?)?)?;
+        Ok(view_container(&content, view))
+    }
+
+    /// Render a layout around the screen's views, `base.liquid` unless
+    /// another is named
+    pub async fn render_layout(
+        &self,
+        pool: &SqlitePool,
+        embed: &str,
+        layout: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let template = self.layout_template(pool, layout).await?;
+        Ok(template.render(&liquid::to_object(&json!({ "embed": embed }))?)?)
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        let state = self.state();
+        state.plugins.len() + state.layouts.len()
+    }
+}
+
+fn markup_hash(markup: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    markup.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The newest modification time of any file under `dir`
+fn newest_modification(dir: &Path) -> Option<SystemTime> {
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter_map(|path| {
+            if path.is_dir() {
+                newest_modification(&path)
+            } else {
+                std::fs::metadata(&path).and_then(|m| m.modified()).ok()
+            }
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        db,
+        models::{
+            partial::{Partial, PartialParams},
+            plugin::PluginParams,
+        },
+    };
+
+    fn templates_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("patina-cache-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("partials")).unwrap();
+        std::fs::write(dir.join("base.liquid"), "<main>{{ embed }}</main>").unwrap();
+        dir
+    }
+
+    fn touch(path: &Path, contents: &str, ahead: u64) {
+        std::fs::write(path, contents).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(ahead))
+            .unwrap();
+    }
+
+    async fn plugin(pool: &SqlitePool, markup: &str) -> Plugin {
+        Plugin::create(
+            pool,
+            &PluginParams {
+                name: "Weather".to_string(),
+                render_markup: Some(markup.to_string()),
+                data_payload: Some(r#"{"temp": 21}"#.to_string()),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reuses_templates_until_invalidated() {
+        let pool = db::test_pool().await;
+        let cache = TemplateCache::new(templates_dir(), false);
+        let mut weather = plugin(&pool, "<p>{{ temp }}</p>").await;
+
+        let view = cache
+            .render_view(&pool, &weather, View::Full)
+            .await
+            .unwrap();
+        assert_eq!(view, r#"<div class="view view--full"><p>21</p></div>"#);
+        let html = cache.render_layout(&pool, &view, None).await.unwrap();
+        assert!(html.starts_with("<main><div"));
+        cache
+            .render_view(&pool, &weather, View::Full)
+            .await
+            .unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // Changed markup hashes differently, so it is never served stale
+        weather.render_markup = Some("<b>{{ temp }}</b>".to_string());
+        let view = cache
+            .render_view(&pool, &weather, View::Full)
+            .await
+            .unwrap();
+        assert!(view.contains("<b>21</b>"));
+        assert_eq!(cache.len(), 3);
+
+        cache.invalidate_plugin(weather.id);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_clear_picks_up_partial_changes() {
+        let pool = db::test_pool().await;
+        let cache = TemplateCache::new(templates_dir(), false);
+        let weather = plugin(&pool, r#"{% render "unit" %}"#).await;
+        assert!(
+            cache
+                .render_view(&pool, &weather, View::Full)
+                .await
+                .is_err()
+        );
+
+        Partial::create(
+            &pool,
+            &PartialParams {
+                name: "unit".to_string(),
+                markup: "°C".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+        cache.clear();
+        let view = cache
+            .render_view(&pool, &weather, View::Full)
+            .await
+            .unwrap();
+        assert!(view.contains("°C"));
+    }
+
+    #[tokio::test]
+    async fn test_dev_mode_reloads_layout() {
+        let pool = db::test_pool().await;
+        let dir = templates_dir();
+        let production = TemplateCache::new(&dir, false);
+        let dev = TemplateCache::new(&dir, true);
+        for cache in [&production, &dev] {
+            let html = cache.render_layout(&pool, "hi", None).await.unwrap();
+            assert_eq!(html, "<main>hi</main>");
+        }
+
+        touch(&dir.join("base.liquid"), "<body>{{ embed }}</body>", 5);
+        let html = production.render_layout(&pool, "hi", None).await.unwrap();
+        assert_eq!(html, "<main>hi</main>");
+        let html = dev.render_layout(&pool, "hi", None).await.unwrap();
+        assert_eq!(html, "<body>hi</body>");
+    }
+}
@@ -0,0 +1,833 @@
+//! Liquid filters used by TRMNL community plugins, on top of the Shopify
+//! standard library.
+
+use liquid::{ParserBuilder, partials::PartialCompiler};
+use liquid_core::{
+    Display_filter, Error, Expression, Filter, FilterParameters, FilterReflection,
+    FromFilterParameters, ParseFilter, Result, Runtime, Value, ValueView,
+    model::{DateTime, ValueViewCmp},
+};
+
+/// Add every custom filter to a parser
+pub fn register<P: PartialCompiler>(builder: ParserBuilder<P>) -> ParserBuilder<P> {
+    builder
+        .filter(NumberWithDelimiter)
+        .filter(LDate)
+        .filter(Pluralize)
+        .filter(Json)
+        .filter(GroupBy)
+        .filter(FindBy)
+        .filter(Sample)
+        .filter(DaysAgo)
+}
+
+fn invalid_input(cause: &str) -> Error {
+    Error::with_msg("Invalid input").context("cause", cause.to_string())
+}
+
+#[derive(Debug, FilterParameters)]
+struct NumberWithDelimiterArgs {
+    #[parameter(description = "Thousands delimiter, `,` by default", arg_type = "str")]
+    delimiter: Option<Expression>,
+    #[parameter(description = "Decimal separator, `.` by default", arg_type = "str")]
+    separator: Option<Expression>,
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "number_with_delimiter",
+    description = "Formats a number with grouped thousands, e.g. 1234567 as 1,234,567.",
+    parameters(NumberWithDelimiterArgs),
+    parsed(NumberWithDelimiterFilter)
+)]
+pub struct NumberWithDelimiter;
+
+#[derive(Debug, FromFilterParameters, Display_filter)]
+#[name = "number_with_delimiter"]
+struct NumberWithDelimiterFilter {
+    #[parameters]
+    args: NumberWithDelimiterArgs,
+}
+
+impl Filter for NumberWithDelimiterFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> Result<Value> {
+        let args = self.args.evaluate(runtime)?;
+        let delimiter = args.delimiter.as_deref().unwrap_or(",");
+        let separator = args.separator.as_deref().unwrap_or(".");
+
+        // Like Rails, anything that isn't a number passes through untouched
+        Ok(input
+            .as_scalar()
+            .and_then(|s| delimit(&s.to_kstr(), delimiter, separator))
+            .map(Value::scalar)
+            .unwrap_or_else(|| input.to_value()))
+    }
+}
+
+fn delimit(number: &str, delimiter: &str, separator: &str) -> Option<String> {
+    let (sign, digits) = match number.trim().strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", number.trim()),
+    };
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(integer) || fraction.is_some_and(|f| !all_digits(f)) {
+        return None;
+    }
+
+    let mut out = sign.to_string();
+    for (i, c) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            out.push_str(delimiter);
+        }
+        out.push(c);
+    }
+    if let Some(fraction) = fraction {
+        out.push_str(separator);
+        out.push_str(fraction);
+    }
+    Some(out)
+}
+
+struct Locale {
+    months: [&'static str; 12],
+    abbr_months: [&'static str; 12],
+    /// Starting from Sunday
+    days: [&'static str; 7],
+    abbr_days: [&'static str; 7],
+}
+
+const EN: Locale = Locale {
+    months: [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ],
+    abbr_months: [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ],
+    days: [
+        "Sunday",
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+    ],
+    abbr_days: ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"],
+};
+
+const DE: Locale = Locale {
+    months: [
+        "Januar",
+        "Februar",
+        "März",
+        "April",
+        "Mai",
+        "Juni",
+        "Juli",
+        "August",
+        "September",
+        "Oktober",
+        "November",
+        "Dezember",
+    ],
+    abbr_months: [
+        "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+    ],
+    days: [
+        "Sonntag",
+        "Montag",
+        "Dienstag",
+        "Mittwoch",
+        "Donnerstag",
+        "Freitag",
+        "Samstag",
+    ],
+    abbr_days: ["So", "Mo", "Di", "Mi", "Do", "Fr", "Sa"],
+};
+
+const FR: Locale = Locale {
+    months: [
+        "janvier",
+        "février",
+        "mars",
+        "avril",
+        "mai",
+        "juin",
+        "juillet",
+        "août",
+        "septembre",
+        "octobre",
+        "novembre",
+        "décembre",
+    ],
+    abbr_months: [
+        "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.", "nov.",
+        "déc.",
+    ],
+    days: [
+        "dimanche", "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi",
+    ],
+    abbr_days: ["dim.", "lun.", "mar.", "mer.", "jeu.", "ven.", "sam."],
+};
+
+const ES: Locale = Locale {
+    months: [
+        "enero",
+        "febrero",
+        "marzo",
+        "abril",
+        "mayo",
+        "junio",
+        "julio",
+        "agosto",
+        "septiembre",
+        "octubre",
+        "noviembre",
+        "diciembre",
+    ],
+    abbr_months: [
+        "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "oct", "nov", "dic",
+    ],
+    days: [
+        "domingo",
+        "lunes",
+        "martes",
+        "miércoles",
+        "jueves",
+        "viernes",
+        "sábado",
+    ],
+    abbr_days: ["dom", "lun", "mar", "mié", "jue", "vie", "sáb"],
+};
+
+const IT: Locale = Locale {
+    months: [
+        "gennaio",
+        "febbraio",
+        "marzo",
+        "aprile",
+        "maggio",
+        "giugno",
+        "luglio",
+        "agosto",
+        "settembre",
+        "ottobre",
+        "novembre",
+        "dicembre",
+    ],
+    abbr_months: [
+        "gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic",
+    ],
+    days: [
+        "domenica",
+        "lunedì",
+        "martedì",
+        "mercoledì",
+        "giovedì",
+        "venerdì",
+        "sabato",
+    ],
+    abbr_days: ["dom", "lun", "mar", "mer", "gio", "ven", "sab"],
+};
+
+const NL: Locale = Locale {
+    months: [
+        "januari",
+        "februari",
+        "maart",
+        "april",
+        "mei",
+        "juni",
+        "juli",
+        "augustus",
+        "september",
+        "oktober",
+        "november",
+        "december",
+    ],
+    abbr_months: [
+        "jan", "feb", "mrt", "apr", "mei", "jun", "jul", "aug", "sep", "okt", "nov", "dec",
+    ],
+    days: [
+        "zondag",
+        "maandag",
+        "dinsdag",
+        "woensdag",
+        "donderdag",
+        "vrijdag",
+        "zaterdag",
+    ],
+    abbr_days: ["zo", "ma", "di", "wo", "do", "vr", "za"],
+};
+
+/// Look up a locale by language, so `de-AT` and `de_DE` both use German.
+/// Unknown languages fall back to English.
+fn locale(name: &str) -> &'static Locale {
+    let language = name.split(['-', '_']).next().unwrap_or_default();
+    match language.to_ascii_lowercase().as_str() {
+        "de" => &DE,
+        "fr" => &FR,
+        "es" => &ES,
+        "it" => &IT,
+        "nl" => &NL,
+        _ => &EN,
+    }
+}
+
+/// Substitute the locale's month and day names into a strftime format, leaving
+/// every other directive for liquid's own formatter
+fn localize_format(format: &str, date: &DateTime, locale: &Locale) -> String {
+    let month = usize::from(date.month()) - 1;
+    let day = usize::from(date.weekday().number_days_from_sunday());
+
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('B') => out.push_str(locale.months[month]),
+            Some('b') | Some('h') => out.push_str(locale.abbr_months[month]),
+            Some('A') => out.push_str(locale.days[day]),
+            Some('a') => out.push_str(locale.abbr_days[day]),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[derive(Debug, FilterParameters)]
+struct LDateArgs {
+    #[parameter(
+        description = "The strftime format to return the date in.",
+        arg_type = "str"
+    )]
+    format: Expression,
+    #[parameter(
+        description = "Language for month and day names, e.g. `de`.",
+        arg_type = "str"
+    )]
+    locale: Option<Expression>,
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "l_date",
+    description = "Formats a date like `date`, with month and day names in the given language.",
+    parameters(LDateArgs),
+    parsed(LDateFilter)
+)]
+pub struct LDate;
+
+#[derive(Debug, FromFilterParameters, Display_filter)]
+#[name = "l_date"]
+struct LDateFilter {
+    #[parameters]
+    args: LDateArgs,
+}
+
+impl Filter for LDateFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> Result<Value> {
+        let args = self.args.evaluate(runtime)?;
+        let Some(date) = input.as_scalar().and_then(|s| s.to_date_time()) else {
+            return Ok(input.to_value());
+        };
+
+        let locale = locale(args.locale.as_deref().unwrap_or("en"));
+        let format = localize_format(&args.format, &date, locale);
+        let formatted = date
+            .format(&format)
+            .map_err(|_| Error::with_msg(format!("Invalid date-format string: {}", args.format)))?;
+        Ok(Value::scalar(formatted))
+    }
+}
+
+#[derive(Debug, FilterParameters)]
+struct PluralizeArgs {
+    #[parameter(description = "How many there are.", arg_type = "integer")]
+    count: Expression,
+    #[parameter(
+        description = "Plural form, when adding an `s` isn't right.",
+        arg_type = "str",
+        mode = "keyword"
+    )]
+    plural: Option<Expression>,
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "pluralize",
+    description = "Prefixes a word with a count, pluralizing it unless the count is 1.",
+    parameters(PluralizeArgs),
+    parsed(PluralizeFilter)
+)]
+pub struct Pluralize;
+
+#[derive(Debug, FromFilterParameters, Display_filter)]
+#[name = "pluralize"]
+struct PluralizeFilter {
+    #[parameters]
+    args: PluralizeArgs,
+}
+
+impl Filter for PluralizeFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> Result<Value> {
+        let args = self.args.evaluate(runtime)?;
+        let singular = input.to_kstr();
+        let word = if args.count == 1 {
+            singular.to_string()
+        } else {
+            match args.plural {
+                Some(plural) => plural.to_string(),
+                None => pluralize(&singular),
+            }
+        };
+        Ok(Value::scalar(format!("{} {}", args.count, word)))
+    }
+}
+
+/// Regular English plurals plus the irregular nouns that turn up in plugins
+fn pluralize(word: &str) -> String {
+    const IRREGULAR: [(&str, &str); 6] = [
+        ("person", "people"),
+        ("child", "children"),
+        ("man", "men"),
+        ("woman", "women"),
+        ("mouse", "mice"),
+        ("foot", "feet"),
+    ];
+    if let Some((_, plural)) = IRREGULAR.iter().find(|(s, _)| s.eq_ignore_ascii_case(word)) {
+        return plural.to_string();
+    }
+
+    let lower = word.to_ascii_lowercase();
+    if let Some(stem) = word.strip_suffix(['y', 'Y'])
+        && !stem.ends_with(['a', 'e', 'i', 'o', 'u'])
+        && !stem.is_empty()
+    {
+        format!("{}ies", stem)
+    } else if ["s", "x", "z", "ch", "sh"]
+        .iter()
+        .any(|suffix| lower.ends_with(suffix))
+    {
+        format!("{}es", word)
+    } else {
+        format!("{}s", word)
+    }
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "json",
+    description = "Serializes a value as JSON, e.g. to hand data to a script.",
+    parsed(JsonFilter)
+)]
+pub struct Json;
+
+#[derive(Debug, Default, Display_filter)]
+#[name = "json"]
+struct JsonFilter;
+
+impl Filter for JsonFilter {
+    fn evaluate(&self, input: &dyn ValueView, _runtime: &dyn Runtime) -> Result<Value> {
+        let json = serde_json::to_string(&input.to_value())
+            .map_err(|err| Error::with_msg(format!("Failed to serialize JSON: {}", err)))?;
+        Ok(Value::scalar(json))
+    }
+}
+
+#[derive(Debug, FilterParameters)]
+struct GroupByArgs {
+    #[parameter(description = "The property to group by.", arg_type = "str")]
+    property: Expression,
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "group_by",
+    description = "Groups an array of objects by a property, as `[value, items]` pairs \
+                   in the order each value first appears.",
+    parameters(GroupByArgs),
+    parsed(GroupByFilter)
+)]
+pub struct GroupBy;
+
+#[derive(Debug, FromFilterParameters, Display_filter)]
+#[name = "group_by"]
+struct GroupByFilter {
+    #[parameters]
+    args: GroupByArgs,
+}
+
+impl Filter for GroupByFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> Result<Value> {
+        let args = self.args.evaluate(runtime)?;
+        let array = input
+            .as_array()
+            .ok_or_else(|| invalid_input("Array expected"))?;
+
+        // Pairs rather than an object so groups keep their order, which also
+        // makes `{% for group in grouped %}{{ group[0] }}` work as it does in Ruby
+        let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+        for item in array.values() {
+            let key = item
+                .as_object()
+                .and_then(|object| object.get(&args.property))
+                .map(|value| value.to_value())
+                .unwrap_or(Value::Nil);
+            match groups
+                .iter_mut()
+                .find(|(k, _)| ValueViewCmp::new(k) == ValueViewCmp::new(&key))
+            {
+                Some((_, items)) => items.push(item.to_value()),
+                None => groups.push((key, vec![item.to_value()])),
+            }
+        }
+
+        Ok(Value::array(groups.into_iter().map(|(key, items)| {
+            Value::array([key, Value::array(items)])
+        })))
+    }
+}
+
+#[derive(Debug, FilterParameters)]
+struct FindByArgs {
+    #[parameter(description = "The property being matched.", arg_type = "str")]
+    property: Expression,
+    #[parameter(
+        description = "The value the property is matched with.",
+        arg_type = "any"
+    )]
+    target_value: Expression,
+    #[parameter(description = "Returned when nothing matches.", arg_type = "any")]
+    fallback: Option<Expression>,
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "find_by",
+    description = "Returns the first object in an array with a certain property value.",
+    parameters(FindByArgs),
+    parsed(FindByFilter)
+)]
+pub struct FindBy;
+
+#[derive(Debug, FromFilterParameters, Display_filter)]
+#[name = "find_by"]
+struct FindByFilter {
+    #[parameters]
+    args: FindByArgs,
+}
+
+impl Filter for FindByFilter {
+    fn evaluate(&self, input: &dyn ValueView, runtime: &dyn Runtime) -> Result<Value> {
+        let args = self.args.evaluate(runtime)?;
+        let array = input
+            .as_array()
+            .ok_or_else(|| invalid_input("Array expected"))?;
+
+        let found = array.values().find(|item| {
+            item.as_object()
+                .and_then(|object| object.get(&args.property))
+                .is_some_and(|value| {
+                    ValueViewCmp::new(value) == ValueViewCmp::new(args.target_value.as_view())
+                })
+        });
+        Ok(match (found, args.fallback) {
+            (Some(item), _) => item.to_value(),
+            (None, Some(fallback)) => fallback.to_value(),
+            (None, None) => Value::Nil,
+        })
+    }
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "sample",
+    description = "Returns a random element of an array.",
+    parsed(SampleFilter)
+)]
+pub struct Sample;
+
+#[derive(Debug, Default, Display_filter)]
+#[name = "sample"]
+struct SampleFilter;
+
+impl Filter for SampleFilter {
+    fn evaluate(&self, input: &dyn ValueView, _runtime: &dyn Runtime) -> Result<Value> {
+        let array = input
+            .as_array()
+            .ok_or_else(|| invalid_input("Array expected"))?;
+        if array.size() == 0 {
+            return Ok(Value::Nil);
+        }
+        let index = rand::random_range(0..array.size());
+        Ok(array
+            .get(index)
+            .map(|item| item.to_value())
+            .unwrap_or(Value::Nil))
+    }
+}
+
+#[derive(Clone, ParseFilter, FilterReflection)]
+#[filter(
+    name = "days_ago",
+    description = "Returns the date and time a number of days before now, for use with `date`.",
+    parsed(DaysAgoFilter)
+)]
+pub struct DaysAgo;
+
+#[derive(Debug, Default, Display_filter)]
+#[name = "days_ago"]
+struct DaysAgoFilter;
+
+impl Filter for DaysAgoFilter {
+    fn evaluate(&self, input: &dyn ValueView, _runtime: &dyn Runtime) -> Result<Value> {
+        let days = input
+            .as_scalar()
+            .and_then(|s| s.to_integer())
+            .ok_or_else(|| invalid_input("Whole number of days expected"))?;
+        let date = days_before(DateTime::now(), days)
+            .ok_or_else(|| invalid_input("Number of days is out of range"))?;
+        Ok(Value::scalar(date))
+    }
+}
+
+/// The date `days` before `date`, `None` when that can't be represented
+fn days_before(mut date: DateTime, days: i64) -> Option<DateTime> {
+    let seconds = days.unsigned_abs().checked_mul(24 * 60 * 60)?;
+    let offset = std::time::Duration::from_secs(seconds).try_into().ok()?;
+    *date = if days >= 0 {
+        date.checked_sub(offset)?
+    } else {
+        date.checked_add(offset)?
+    };
+    Some(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(template: &str, data: serde_json::Value) -> String {
+        let parser = register(liquid::ParserBuilder::with_stdlib())
+            .build()
+            .unwrap();
+        let template = parser.parse(template).unwrap();
+        template.render(&liquid::to_object(&data).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_number_with_delimiter() {
+        assert_eq!(
+            render("{{ 1234567 | number_with_delimiter }}", json!({})),
+            "1,234,567"
+        );
+        assert_eq!(
+            render("{{ 999 | number_with_delimiter }}", json!({})),
+            "999"
+        );
+        assert_eq!(
+            render("{{ n | number_with_delimiter }}", json!({"n": -1234.5})),
+            "-1,234.5"
+        );
+        assert_eq!(
+            render(
+                r#"{{ "12345678.05" | number_with_delimiter: ".", "," }}"#,
+                json!({})
+            ),
+            "12.345.678,05"
+        );
+        assert_eq!(
+            render(r#"{{ "n/a" | number_with_delimiter }}"#, json!({})),
+            "n/a"
+        );
+    }
+
+    #[test]
+    fn test_l_date() {
+        let data = json!({"date": "2025-03-03 08:30:00 +0000"});
+        assert_eq!(
+            render(r#"{{ date | l_date: "%A %-d %B" }}"#, data.clone()),
+            "Monday 3 March"
+        );
+        assert_eq!(
+            render(r#"{{ date | l_date: "%A %-d %B %Y", "de" }}"#, data.clone()),
+            "Montag 3 März 2025"
+        );
+        assert_eq!(
+            render(r#"{{ date | l_date: "%a %d %b", "fr-CA" }}"#, data.clone()),
+            "lun. 03 mars"
+        );
+        assert_eq!(
+            render(r#"{{ date | l_date: "%H:%M %%B", "xx" }}"#, data),
+            "08:30 %B"
+        );
+        assert_eq!(render(r#"{{ "soon" | l_date: "%Y" }}"#, json!({})), "soon");
+    }
+
+    #[test]
+    fn test_pluralize() {
+        assert_eq!(
+            render(r#"{{ "book" | pluralize: 1 }}"#, json!({})),
+            "1 book"
+        );
+        assert_eq!(
+            render(r#"{{ "book" | pluralize: 0 }}"#, json!({})),
+            "0 books"
+        );
+        assert_eq!(
+            render(r#"{{ "city" | pluralize: n }}"#, json!({"n": 3})),
+            "3 cities"
+        );
+        assert_eq!(
+            render(r#"{{ "box" | pluralize: 2 }}"#, json!({})),
+            "2 boxes"
+        );
+        assert_eq!(render(r#"{{ "day" | pluralize: 2 }}"#, json!({})), "2 days");
+        assert_eq!(
+            render(r#"{{ "person" | pluralize: 4 }}"#, json!({})),
+            "4 people"
+        );
+        assert_eq!(
+            render(
+                r#"{{ "octopus" | pluralize: 2, plural: "octopodes" }}"#,
+                json!({})
+            ),
+            "2 octopodes"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let data = json!({"temps": [18, 21.5], "city": "Oslo"});
+        let rendered = render("{{ data | json }}", json!({ "data": data }));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&rendered).unwrap(),
+            data
+        );
+        assert_eq!(
+            render("{{ s | json }}", json!({"s": "a \"b\""})),
+            r#""a \"b\"""#
+        );
+    }
+
+    #[test]
+    fn test_group_by() {
+        let data = json!({"items": [
+            {"name": "Milk", "aisle": "dairy"},
+            {"name": "Apples", "aisle": "produce"},
+            {"name": "Cheese", "aisle": "dairy"},
+        ]});
+        assert_eq!(
+            render(
+                r#"{% assign groups = items | group_by: "aisle" %}{% for group in groups %}{{ group[0] }}:{{ group[1] | map: "name" | join: "," }};{% endfor %}"#,
+                data,
+            ),
+            "dairy:Milk,Cheese;produce:Apples;"
+        );
+    }
+
+    #[test]
+    fn test_find_by() {
+        let data = json!({"people": [
+            {"name": "Ada", "age": 36},
+            {"name": "Grace", "age": 85},
+        ]});
+        assert_eq!(
+            render(
+                r#"{% assign p = people | find_by: "name", "Grace" %}{{ p.age }}"#,
+                data.clone()
+            ),
+            "85"
+        );
+        assert_eq!(
+            render(
+                r#"{% assign p = people | find_by: "age", 36 %}{{ p.name }}"#,
+                data.clone()
+            ),
+            "Ada"
+        );
+        assert_eq!(
+            render(
+                r#"{{ people | find_by: "name", "Alan", "nobody" }}"#,
+                data.clone()
+            ),
+            "nobody"
+        );
+        assert_eq!(
+            render(r#"{{ people | find_by: "name", "Alan" }}"#, data),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        let data = json!({"one": ["only"], "many": ["a", "b", "c"], "none": []});
+        assert_eq!(render("{{ one | sample }}", data.clone()), "only");
+        assert_eq!(render("{{ none | sample }}", data.clone()), "");
+        for _ in 0..20 {
+            let picked = render("{{ many | sample }}", data.clone());
+            assert!(["a", "b", "c"].contains(&picked.as_str()), "{}", picked);
+        }
+    }
+
+    #[test]
+    fn test_days_ago() {
+        let now = DateTime::from_ymd(2025, 3, 3);
+        assert_eq!(days_before(now, 3), Some(DateTime::from_ymd(2025, 2, 28)));
+        assert_eq!(days_before(now, -1), Some(DateTime::from_ymd(2025, 3, 4)));
+
+        // Either side of midnight, should the test straddle it
+        let before = days_before(DateTime::now(), 2)
+            .unwrap()
+            .format("%Y-%m-%d")
+            .unwrap();
+        let rendered = render(r#"{{ 2 | days_ago | date: "%Y-%m-%d" }}"#, json!({}));
+        let after = days_before(DateTime::now(), 2)
+            .unwrap()
+            .format("%Y-%m-%d")
+            .unwrap();
+        assert!(rendered == before || rendered == after, "{}", rendered);
+    }
+
+    #[test]
+    fn test_days_ago_out_of_range() {
+        let now = DateTime::from_ymd(2025, 3, 3);
+        for days in [i64::MAX, i64::MIN, 10_000_000, -10_000_000] {
+            assert_eq!(days_before(now, days), None, "{}", days);
+        }
+
+        let parser = register(liquid::ParserBuilder::with_stdlib())
+            .build()
+            .unwrap();
+        let template = parser
+            .parse(&format!("{{{{ {} | days_ago }}}}", i64::MAX))
+            .unwrap();
+        let err = template.render(&liquid::Object::new()).unwrap_err();
+        assert!(err.to_string().contains("out of range"), "{}", err);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use log::{debug, info, warn};
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    storage::{ImageStorage, Storage},
+};
+
+/// Totals across every collection since the server started
+#[derive(Debug, Default)]
+pub struct GcMetrics {
+    pub runs: AtomicU64,
+    pub files_removed: AtomicU64,
+    pub bytes_reclaimed: AtomicU64,
+}
+
+impl GcMetrics {
+    fn record(&self, report: &GcReport) {
+        self.runs.fetch_add(1, Ordering::Relaxed);
+        self.files_removed
+            .fetch_add(report.files_removed, Ordering::Relaxed);
+        self.bytes_reclaimed
+            .fetch_add(report.bytes_reclaimed, Ordering::Relaxed);
+    }
+}
+
+/// What a single collection removed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GcReport {
+    pub files_removed: u64,
+    pub bytes_reclaimed: u64,
+}
+
+/// Image ids still shown on a device, kept as a plugin's or mashup's current
+/// screen or listed in a device's screen history
+async fn referenced_images(pool: &SqlitePool) -> Result<HashSet<String>, sqlx::Error> {
+    let ids: Vec<(String,)> = sqlx::query_as(
+        "SELECT current_screen_image FROM devices WHERE current_screen_image IS NOT NULL
+         UNION SELECT current_image FROM plugins WHERE current_image IS NOT NULL
+         UNION SELECT current_image FROM mashups WHERE current_image IS NOT NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+    // History names the file sent, `<id>.<format>`
+    let shown: Vec<(String,)> = sqlx::query_as("SELECT DISTINCT filename FROM screen_history")
+        .fetch_all(pool)
+        .await?;
+    let shown = shown
+        .into_iter()
+        .map(|(filename,)| match filename.rsplit_once('.') {
+            Some((id, _)) => id.to_string(),
+            None => filename,
+        });
+    Ok(ids.into_iter().map(|(id,)| id).chain(shown).collect())
+}
+
+/// Delete the stored PNGs and BMPs that nothing references and that haven't
+/// been written or reused within `retention`
+pub async fn collect(
+    pool: &SqlitePool,
+    storage: &dyn ImageStorage,
+    retention: Duration,
+) -> Result<GcReport, anyhow::Error> {
+    let referenced = referenced_images(pool).await?;
+    let cutoff = SystemTime::now()
+        .checked_sub(retention)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    let mut report = GcReport::default();
+    for object in storage.list().await? {
+        let Some((id, "png" | "bmp")) = object.key.rsplit_once('.') else {
+            continue;
+        };
+        if referenced.contains(id) || object.modified > cutoff {
+            continue;
+        }
+        match storage.delete(&object.key).await {
+            Ok(()) => {
+                debug!("removed {}", storage.location(&object.key));
+                report.files_removed += 1;
+                report.bytes_reclaimed += object.size;
+            }
+            Err(err) => warn!("failed to remove {}: {:#}", object.key, err),
+        }
+    }
+    Ok(report)
+}
+
+/// Collect unreferenced images every `gc.interval_minutes`
+pub async fn run(pool: SqlitePool, config: Arc<Config>, storage: Storage, metrics: Arc<GcMetrics>) {
+    let retention = Duration::from_secs(config.gc.retention_hours * 60 * 60);
+    let mut interval = tokio::time::interval(Duration::from_secs(config.gc.interval_minutes * 60));
+    loop {
+        interval.tick().await;
+        match collect(&pool, storage.as_ref(), retention).await {
+            Ok(report) => {
+                metrics.record(&report);
+                if report.files_removed > 0 {
+                    info!(
+                        "removed {} unused images, reclaiming {} bytes ({} bytes in total)",
+                        report.files_removed,
+                        report.bytes_reclaimed,
+                        metrics.bytes_reclaimed.load(Ordering::Relaxed)
+                    );
+                }
+            }
+            Err(err) => warn!("image cleanup failed: {:#}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RenderConfig,
+        db,
+        models::{
+            device::{Device, MacAddress},
+            plugin::{Plugin, PluginParams},
+            screen::ScreenRecord,
+        },
+        storage::local::LocalStorage,
+    };
+
+    fn write(dir: &std::path::Path, name: &str, age: Duration) {
+        let path = dir.join(name);
+        std::fs::write(&path, "image").unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_removes_old_unreferenced_images() {
+        let pool = db::test_pool().await;
+        let dir = std::env::temp_dir().join(format!("patina-gc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        let plugin = Plugin::create(
+            &pool,
+            &PluginParams {
+                name: "Weather".to_string(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        Plugin::set_current_image(&pool, plugin.id, "kept")
+            .await
+            .unwrap();
+
+        for name in ["kept.png", "kept.bmp", "old.png", "old.bmp"] {
+            write(&dir, name, 2 * day);
+        }
+        write(&dir, "recent.png", Duration::from_secs(60));
+        write(&dir, "notes.txt", 2 * day);
+
+        let storage = LocalStorage::new(&dir).unwrap();
+        let report = collect(&pool, &storage, day).await.unwrap();
+        assert_eq!(
+            report,
+            GcReport {
+                files_removed: 2,
+                bytes_reclaimed: 10
+            }
+        );
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["kept.bmp", "kept.png", "notes.txt", "recent.png"]);
+
+        let metrics = GcMetrics::default();
+        metrics.record(&report);
+        metrics.record(&collect(&pool, &storage, day).await.unwrap());
+        assert_eq!(metrics.runs.load(Ordering::Relaxed), 2);
+        assert_eq!(metrics.bytes_reclaimed.load(Ordering::Relaxed), 10);
+    }
+
+    #[tokio::test]
+    async fn test_keeps_images_in_screen_history() {
+        let pool = db::test_pool().await;
+        let dir = std::env::temp_dir().join(format!("patina-gc-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let day = Duration::from_secs(24 * 60 * 60);
+
+        let mac: MacAddress = "AA:BB:CC:DD:EE:FF".parse().unwrap();
+        let device = Device::create(
+            &pool,
+            &mac,
+            "key",
+            "device-EE:FF",
+            "Kitchen",
+            &RenderConfig::default(),
+        )
+        .await
+        .unwrap();
+        ScreenRecord::record(&pool, device.id, "shown.bmp")
+            .await
+            .unwrap();
+
+        for name in ["shown.png", "shown.bmp", "old.png"] {
+            write(&dir, name, 2 * day);
+        }
+
+        let storage = LocalStorage::new(&dir).unwrap();
+        let report = collect(&pool, &storage, day).await.unwrap();
+        assert_eq!(report.files_removed, 1);
+        let mut left: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left, ["shown.bmp", "shown.png"]);
+    }
+}
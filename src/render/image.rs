@@ -1,175 +1,485 @@
-use std::{
-    ffi::OsStr,
-    path::{Path, PathBuf},
-};
+use std::{ffi::OsStr, io::Cursor, net::Ipv4Addr, path::Path, time::Instant};
 
+use anyhow::{Context, bail};
+use axum::{Router, response::Html, routing::get};
 use headless_chrome::{Browser, LaunchOptions, protocol::cdp::Page};
 use image::{
-    ImageFormat,
+    DynamicImage, ImageFormat,
     imageops::{BiLevel, dither},
-    load_from_memory_with_format,
+    load_from_memory, load_from_memory_with_format,
 };
 use log::debug;
-use tracing::info;
+use sha2::{Digest, Sha256};
+use tower_http::services::ServeDir;
 
-use crate::render::template::basic_template;
+use crate::{
+    config::{Config, RenderConfig},
+    metrics::METRICS,
+    storage::ImageStorage,
+};
 
-#[derive(Debug, Clone)]
+/// A rendered screen, kept in the image storage as `<id>.png` and `<id>.bmp`
+/// and converted to other formats when a device asks for one
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RenderedImage {
-    pub png_path: PathBuf,
-    pub bmp_path: PathBuf,
+    id: String,
+}
+
+/// Encodings a screen can be delivered to a device in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenFormat {
+    Png,
+    Bmp,
+}
+
+impl ScreenFormat {
+    pub const ALL: [ScreenFormat; 2] = [ScreenFormat::Png, ScreenFormat::Bmp];
+
+    /// The format named by a device's `image_format`, `None` for `auto` or
+    /// anything unknown
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == name)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ScreenFormat::Png => "png",
+            ScreenFormat::Bmp => "bmp",
+        }
+    }
+
+    fn image_format(self) -> ImageFormat {
+        match self {
+            ScreenFormat::Png => ImageFormat::Png,
+            ScreenFormat::Bmp => ImageFormat::Bmp,
+        }
+    }
+}
+
+/// The dithered screenshot of a document, encoded for both firmware generations
+pub struct Screen {
+    pub png: Vec<u8>,
+    pub bmp: Vec<u8>,
 }
 
 impl RenderedImage {
-    pub fn default() -> Self {
-        let id = uuid::Uuid::new_v4();
-        let generated_dir = Path::new("assets/images/generated");
-        let png_path = generated_dir.join(id.to_string()).with_extension("png");
-        let bmp_path = generated_dir.join(id.to_string()).with_extension("bmp");
-        Self { png_path, bmp_path }
-    }
-
-    pub async fn render(&self, url: &str) -> Result<(), anyhow::Error> {
-        // self.render_images(url).await?;
-        info!(url);
-        self.render_html().await?;
-        Ok(())
-    }
-
-    async fn render_html(&self) -> Result<(), anyhow::Error> {
-        let rendered = basic_template()?;
-        println!("{}", rendered);
-        let base = OsStr::new("--hide-scrollbars");
-        let args = vec![base];
-
-        // Create browser with custom window size
-        let launch_options = LaunchOptions::default_builder()
-            .window_size(Some((800, 480)))
-            .headless(true)
-            .sandbox(false)
-            .args(args)
-            .build()?;
-
-        debug!("starting browser");
-        let browser = Browser::new(launch_options);
-        let browser = match browser {
-            Err(err) => panic!("{}", err),
-            Ok(browser) => browser,
-        };
+    /// An image named after a hash of the final document and the screen
+    /// geometry, so an unchanged screen keeps its filename and devices can
+    /// skip refreshing the panel
+    pub fn for_html(html: &str, render: &RenderConfig) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(render.width.to_le_bytes());
+        hasher.update(render.height.to_le_bytes());
+        hasher.update(html.as_bytes());
+        let id = hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        Self { id }
+    }
 
-        // Navigate to the URL and take screenshot
-        let tab = browser.new_tab()?;
-        let data_url = format!(
-            "data:text/html;charset=utf-8,{}",
-            urlencoding::encode(rendered.as_str())
-        );
+    /// The image stored as `id`, e.g. a device's `current_screen_image`
+    pub fn from_id(id: &str) -> Self {
+        Self { id: id.to_string() }
+    }
+
+    /// The file stem shared by the PNG and BMP, as stored in `current_screen_image`
+    pub fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    pub fn key(&self, format: ScreenFormat) -> String {
+        format!("{}.{}", self.id, format.extension())
+    }
 
-        tab.call_method(Page::SetDeviceMetricsOverride {
-            width: 800,
-            height: 480,
-            device_scale_factor: 1.0,
-            mobile: false,
-            scale: None,
-            screen_width: Some(800),
-            screen_height: Some(480),
-            position_x: None,
-            position_y: None,
-            dont_set_visible_size: None,
-            screen_orientation: None,
-            viewport: None,
-        })?;
-
-        tab.navigate_to(&data_url)?;
-        tab.wait_until_navigated()?;
-
-        debug!("capturing screenshot");
-        // Take screenshot and save to filesystem
-        // Replace this with a Pathbuf
-        let screenshot_data = tab.capture_screenshot(
-            headless_chrome::protocol::cdp::Page::CaptureScreenshotFormatOption::Png,
-            None,
-            None,
-            true,
-        )?;
-
-        let png = load_from_memory_with_format(&screenshot_data, ImageFormat::Png);
-        match png {
-            Ok(png) => {
-                let mut grayscale = png.grayscale();
-                let mut grayscale = grayscale.as_mut_luma8().unwrap();
-                dither(&mut grayscale, &BiLevel);
-                grayscale.save(&self.png_path).unwrap();
-                grayscale.save(&self.bmp_path).unwrap();
+    pub fn png_key(&self) -> String {
+        self.key(ScreenFormat::Png)
+    }
+
+    pub fn bmp_key(&self) -> String {
+        self.key(ScreenFormat::Bmp)
+    }
+
+    /// Make sure the image is stored in `format`, converting it from a copy
+    /// in another format when it is missing
+    pub async fn ensure_format(
+        &self,
+        storage: &dyn ImageStorage,
+        format: ScreenFormat,
+    ) -> Result<(), anyhow::Error> {
+        let key = self.key(format);
+        if storage.exists(&key).await? {
+            return Ok(());
+        }
+        for source in ScreenFormat::ALL {
+            if source == format {
+                continue;
+            }
+            if let Some(bytes) = storage.get(&self.key(source)).await? {
+                debug!("converting {} to {}", self.key(source), key);
+                storage.put(&key, convert(&bytes, format)?).await?;
+                return Ok(());
             }
-            Err(err) => return Err(err.into()),
         }
-        debug!("wrote PNG");
-        Ok(())
-    }
-
-    async fn render_images(&self, url: &str) -> Result<(), anyhow::Error> {
-        // Not sure why we have to do this but it works
-        let base = OsStr::new("--hide-scrollbars");
-        let args = vec![base];
-
-        // Create browser with custom window size
-        let launch_options = LaunchOptions::default_builder()
-            .window_size(Some((800, 480)))
-            .headless(true)
-            .sandbox(false)
-            .args(args)
-            .build()?;
-
-        debug!("starting browser");
-        let browser = Browser::new(launch_options);
-        let browser = match browser {
-            Err(err) => panic!("{}", err),
-            Ok(browser) => browser,
-        };
+        bail!("no stored copy of screen {}", self.id)
+    }
+
+    /// Render a document into `storage`, reusing the image from an earlier
+    /// render of the identical document
+    pub async fn render_content(
+        html: &str,
+        config: &Config,
+        storage: &dyn ImageStorage,
+    ) -> Result<Self, anyhow::Error> {
+        let image = Self::for_html(html, &config.render);
+        let (png_key, bmp_key) = (image.png_key(), image.bmp_key());
+        if storage.exists(&png_key).await? && storage.exists(&bmp_key).await? {
+            debug!("screen {} unchanged, reusing it", image.id);
+            // Counts as fresh for the garbage collector's retention window
+            storage.touch(&png_key).await?;
+            storage.touch(&bmp_key).await?;
+        } else {
+            let started = Instant::now();
+            let screen = screenshot(html, config).await;
+            METRICS.record_render(started.elapsed(), screen.is_ok());
+            let screen = screen?;
+            storage.put(&png_key, screen.png).await?;
+            storage.put(&bmp_key, screen.bmp).await?;
+        }
+        Ok(image)
+    }
+}
+
+/// Screenshot a complete HTML document and dither it to black and white.
+///
+/// The document is served from a loopback HTTP origin, where relative URLs
+/// such as `framework/plugins.css` resolve to vendored copies in the assets
+/// directory, without letting the page read anything else from the
+/// filesystem. Beyond that origin only the framework hosts can be reached.
+pub async fn screenshot(html: &str, config: &Config) -> Result<Screen, anyhow::Error> {
+    let page = RenderPage::serve(&config.paths.assets_dir, html).await?;
+    let (url, render) = (page.url.clone(), config.render.clone());
+    tokio::task::spawn_blocking(move || capture(&url, &render)).await?
+}
+
+/// Hosts `base.liquid` loads the framework and the Inter font from, the
+/// only ones a render may reach besides its own page
+pub const FRAMEWORK_HOSTS: [&str; 2] = ["usetrmnl.com", "fonts.bunny.net"];
+
+/// Nothing listens here, so requests sent through it fail
+const BLOCKING_PROXY: &str = "127.0.0.1:9";
+
+/// Send every request that isn't for the render page at `page` (`host:port`)
+/// or a framework host to a proxy that doesn't exist, so markup can't fetch
+/// anything else. `<-loopback>` stops Chrome from letting every loopback
+/// address bypass the proxy.
+fn network_args(page: Option<&str>) -> Vec<String> {
+    let mut bypass = vec!["<-loopback>"];
+    bypass.extend(page);
+    bypass.extend(FRAMEWORK_HOSTS);
+    vec![
+        format!("--proxy-server={}", BLOCKING_PROXY),
+        format!("--proxy-bypass-list={}", bypass.join(";")),
+        // WebRTC can otherwise send UDP around the proxy
+        "--force-webrtc-ip-handling-policy=disable_non_proxied_udp".to_string(),
+    ]
+}
+
+/// Start a headless browser sized to the screen that can only reach the
+/// render page at `page` and the framework hosts
+fn launch(render: &RenderConfig, page: Option<&str>) -> Result<Browser, anyhow::Error> {
+    let network = network_args(page);
+    let mut args = vec![OsStr::new("--hide-scrollbars")];
+    args.extend(network.iter().map(OsStr::new));
+
+    // Create browser with custom window size
+    let launch_options = LaunchOptions::default_builder()
+        .window_size(Some((render.width, render.height)))
+        .headless(true)
+        .sandbox(false)
+        .path(render.chrome_path.clone())
+        .args(args)
+        .build()?;
+    Browser::new(launch_options)
+}
+
+/// Start and stop a browser to prove screens can be rendered
+pub async fn check_browser(render: &RenderConfig) -> Result<(), anyhow::Error> {
+    let render = render.clone();
+    tokio::task::spawn_blocking(move || launch(&render, None).map(drop)).await?
+}
 
-        // Navigate to the URL and take screenshot
-        let tab = browser.new_tab()?;
-
-        tab.call_method(Page::SetDeviceMetricsOverride {
-            width: 800,
-            height: 480,
-            device_scale_factor: 1.0,
-            mobile: false,
-            scale: None,
-            screen_width: Some(800),
-            screen_height: Some(480),
-            position_x: None,
-            position_y: None,
-            dont_set_visible_size: None,
-            screen_orientation: None,
-            viewport: None,
-        })?;
-
-        tab.navigate_to(&url)?;
-        tab.wait_until_navigated()?;
-
-        debug!("capturing screenshot");
-        // Take screenshot and save to filesystem
-        // Replace this with a Pathbuf
-        let screenshot_data = tab.capture_screenshot(
-            headless_chrome::protocol::cdp::Page::CaptureScreenshotFormatOption::Png,
-            None,
-            None,
-            true,
-        )?;
-
-        let png = load_from_memory_with_format(&screenshot_data, ImageFormat::Png);
-        match png {
-            Ok(png) => {
-                let mut grayscale = png.grayscale();
-                let mut grayscale = grayscale.as_mut_luma8().unwrap();
-                dither(&mut grayscale, &BiLevel);
-                grayscale.save(&self.png_path).unwrap();
-                grayscale.save(&self.bmp_path).unwrap();
+fn capture(url: &str, render: &RenderConfig) -> Result<Screen, anyhow::Error> {
+    let page = reqwest::Url::parse(url)?;
+    let page = match (page.host_str(), page.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        _ => bail!("render page {} has no host and port", url),
+    };
+    debug!("starting browser");
+    let _running = METRICS.browser_started();
+    let browser = launch(render, Some(&page))?;
+
+    // Navigate to the URL and take screenshot
+    let tab = browser.new_tab()?;
+
+    tab.call_method(Page::SetDeviceMetricsOverride {
+        width: render.width,
+        height: render.height,
+        device_scale_factor: 1.0,
+        mobile: false,
+        scale: None,
+        screen_width: Some(render.width),
+        screen_height: Some(render.height),
+        position_x: None,
+        position_y: None,
+        dont_set_visible_size: None,
+        screen_orientation: None,
+        viewport: None,
+    })?;
+
+    tab.navigate_to(url)?;
+    tab.wait_until_navigated()?;
+
+    debug!("capturing screenshot");
+    let screenshot_data = tab.capture_screenshot(
+        headless_chrome::protocol::cdp::Page::CaptureScreenshotFormatOption::Png,
+        None,
+        None,
+        true,
+    )?;
+
+    let png = load_from_memory_with_format(&screenshot_data, ImageFormat::Png)?;
+    let mut grayscale = png.grayscale().into_luma8();
+    dither(&mut grayscale, &BiLevel);
+    let grayscale = DynamicImage::ImageLuma8(grayscale);
+    Ok(Screen {
+        png: encode(&grayscale, ScreenFormat::Png.image_format())?,
+        bmp: encode(&grayscale, ScreenFormat::Bmp.image_format())?,
+    })
+}
+
+/// Re-encode a stored screen, e.g. the setup image, in another format
+pub fn convert(bytes: &[u8], format: ScreenFormat) -> Result<Vec<u8>, anyhow::Error> {
+    // Screens are already dithered, this only drops the channels a
+    // converted PNG may have picked up
+    let image = DynamicImage::ImageLuma8(load_from_memory(bytes)?.into_luma8());
+    encode(&image, format.image_format())
+}
+
+fn encode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>, anyhow::Error> {
+    let mut bytes = Cursor::new(Vec::new());
+    image.write_to(&mut bytes, format)?;
+    Ok(bytes.into_inner())
+}
+
+/// A rendered document served to the browser on an ephemeral loopback port
+/// together with the vendored framework, stopped again when dropped
+struct RenderPage {
+    url: String,
+    server: tokio::task::JoinHandle<()>,
+}
+
+impl RenderPage {
+    async fn serve(assets_dir: &Path, html: &str) -> Result<Self, anyhow::Error> {
+        let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .context("failed to listen for the render page")?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let html = html.to_string();
+        let app = Router::new()
+            .route("/", get(move || async move { Html(html) }))
+            .nest_service("/framework", ServeDir::new(assets_dir.join("framework")));
+        let server = tokio::spawn(async move {
+            if let Err(err) = axum::serve(listener, app).await {
+                debug!("render page server stopped: {}", err);
             }
-            Err(err) => return Err(err.into()),
+        });
+        Ok(Self { url, server })
+    }
+}
+
+impl Drop for RenderPage {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::local::LocalStorage;
+
+    #[test]
+    fn test_name_depends_on_content_and_geometry() {
+        let render = RenderConfig::default();
+        let image = RenderedImage::for_html("<p>21</p>", &render);
+        assert_eq!(image.id().len(), 64);
+        assert_eq!(image.png_key(), format!("{}.png", image.id()));
+        assert_eq!(image, RenderedImage::for_html("<p>21</p>", &render));
+        assert_ne!(image, RenderedImage::for_html("<p>22</p>", &render));
+
+        let large = RenderConfig {
+            width: 1872,
+            height: 1404,
+            ..RenderConfig::default()
+        };
+        assert_ne!(image, RenderedImage::for_html("<p>21</p>", &large));
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_content_is_not_rendered_again() {
+        let config = Config::for_tests();
+        let storage = LocalStorage::new(
+            std::env::temp_dir().join(format!("patina-generated-{}", uuid::Uuid::new_v4())),
+        )
+        .unwrap();
+        let image = RenderedImage::for_html("<p>21</p>", &config.render);
+        storage
+            .put(&image.png_key(), b"png".to_vec())
+            .await
+            .unwrap();
+        storage
+            .put(&image.bmp_key(), b"bmp".to_vec())
+            .await
+            .unwrap();
+
+        // Would need a browser if it rendered
+        let reused = RenderedImage::render_content("<p>21</p>", &config, &storage)
+            .await
+            .unwrap();
+        assert_eq!(reused, image);
+        assert_eq!(
+            storage.get(&image.png_key()).await.unwrap(),
+            Some(b"png".to_vec())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_converts_missing_formats_on_demand() {
+        let storage = LocalStorage::new(
+            std::env::temp_dir().join(format!("patina-generated-{}", uuid::Uuid::new_v4())),
+        )
+        .unwrap();
+        let image = RenderedImage::from_id("abc");
+        assert!(
+            image
+                .ensure_format(&storage, ScreenFormat::Bmp)
+                .await
+                .is_err()
+        );
+
+        let screen = DynamicImage::ImageLuma8(image::GrayImage::new(8, 4));
+        storage
+            .put(&image.png_key(), encode(&screen, ImageFormat::Png).unwrap())
+            .await
+            .unwrap();
+        image
+            .ensure_format(&storage, ScreenFormat::Bmp)
+            .await
+            .unwrap();
+        let bmp = storage.get(&image.bmp_key()).await.unwrap().unwrap();
+        let converted = load_from_memory_with_format(&bmp, ImageFormat::Bmp).unwrap();
+        assert_eq!((converted.width(), converted.height()), (8, 4));
+
+        // Existing copies are left alone
+        storage
+            .put(&image.png_key(), b"png".to_vec())
+            .await
+            .unwrap();
+        image
+            .ensure_format(&storage, ScreenFormat::Png)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get(&image.png_key()).await.unwrap(),
+            Some(b"png".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_screen_format_names() {
+        assert_eq!(ScreenFormat::from_name("bmp"), Some(ScreenFormat::Bmp));
+        assert_eq!(ScreenFormat::from_name("png"), Some(ScreenFormat::Png));
+        assert_eq!(ScreenFormat::from_name("auto"), None);
+        assert_eq!(ScreenFormat::from_name("jpeg"), None);
+    }
+
+    #[tokio::test]
+    async fn test_render_page_serves_only_page_and_framework() {
+        let dir = std::env::temp_dir().join(format!("patina-page-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("framework")).unwrap();
+        std::fs::write(dir.join("framework/plugins.css"), "body {}").unwrap();
+        std::fs::write(dir.join("setup-logo.bmp"), "BM").unwrap();
+        let page = RenderPage::serve(&dir, "<p>hi</p>").await.unwrap();
+        let url = reqwest::Url::parse(&page.url).unwrap();
+        assert_eq!(url.scheme(), "http");
+        assert_eq!(url.host_str(), Some("127.0.0.1"));
+
+        let fetch = |path: &str| {
+            let url = url.join(path).unwrap();
+            async move { reqwest::get(url).await.unwrap() }
+        };
+        assert_eq!(fetch("").await.text().await.unwrap(), "<p>hi</p>");
+        let css = fetch("framework/plugins.css").await;
+        assert!(css.status().is_success());
+        assert_eq!(css.text().await.unwrap(), "body {}");
+        assert_eq!(fetch("setup-logo.bmp").await.status(), 404);
+        assert_eq!(fetch("framework/../setup-logo.bmp").await.status(), 404);
+
+        drop(page);
+        tokio::task::yield_now().await;
+        assert!(reqwest::get(url).await.is_err());
+        // Nothing was written next to the assets
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_network_args_block_other_hosts() {
+        let args = network_args(Some("127.0.0.1:4321"));
+        assert!(args.contains(&"--proxy-server=127.0.0.1:9".to_string()));
+        assert!(
+            args.contains(
+                &"--proxy-bypass-list=<-loopback>;127.0.0.1:4321;usetrmnl.com;fonts.bunny.net"
+                    .to_string()
+            )
+        );
+        assert!(
+            network_args(None).contains(
+                &"--proxy-bypass-list=<-loopback>;usetrmnl.com;fonts.bunny.net".to_string()
+            )
+        );
+    }
+
+    /// Needs Chrome, so it passes without checking anything where there is none
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_render_does_not_reach_other_hosts() {
+        let mut config = Config::for_tests();
+        if config.render.chrome_path.is_none()
+            && headless_chrome::browser::default_executable().is_err()
+        {
+            eprintln!("skipping, Chrome is not installed");
+            return;
         }
-        debug!("wrote PNG");
-        Ok(())
+        let dir = std::env::temp_dir().join(format!("patina-offline-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        config.paths.assets_dir = dir.clone();
+
+        // Another origin, even one on this machine, is off limits
+        let outside = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .unwrap();
+        let html = format!(
+            r#"<img src="http://{}/pixel.png">"#,
+            outside.local_addr().unwrap()
+        );
+        screenshot(&html, &config).await.unwrap();
+        let reached =
+            tokio::time::timeout(std::time::Duration::from_millis(500), outside.accept()).await;
+        assert!(reached.is_err(), "the render requested another host");
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,214 @@
+use anyhow::{anyhow, bail};
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    models::{
+        mashup::{Mashup, MashupLayout},
+        plugin::Plugin,
+    },
+    render::{cache::TemplateCache, image::RenderedImage},
+    storage::ImageStorage,
+};
+
+/// Render every plugin of a mashup into its slot and record the result as
+/// the mashup's current image.
+pub async fn render_mashup(
+    pool: &SqlitePool,
+    config: &Config,
+    templates: &TemplateCache,
+    storage: &dyn ImageStorage,
+    mashup: &Mashup,
+) -> Result<RenderedImage, anyhow::Error> {
+    let layout = mashup.layout().map_err(|err| anyhow!(err))?;
+    let plugins = mashup.plugins(pool).await?;
+    let html = mashup_html(pool, templates, layout, &plugins).await?;
+    let image = RenderedImage::render_content(&html, config, storage).await?;
+    Mashup::set_current_image(pool, mashup.id, &image.id()).await?;
+    Ok(image)
+}
+
+/// Build the HTML document for plugins arranged in `layout`, each drawn with
+/// its markup for the view size of its slot
+pub async fn mashup_html(
+    pool: &SqlitePool,
+    templates: &TemplateCache,
+    layout: MashupLayout,
+    plugins: &[Plugin],
+) -> Result<String, anyhow::Error> {
+    let views = layout.views();
+    if plugins.len() != views.len() {
+        bail!(
+            "the {} layout needs {} plugins, got {}",
+            layout,
+            views.len(),
+            plugins.len()
+        );
+    }
+
+    let mut embed = format!(r#"<div class="mashup mashup--{}">"#, layout);
+    for (plugin, view) in plugins.iter().zip(views) {
+        embed.push_str(&templates.render_view(pool, plugin, *view).await?);
+    }
+    embed.push_str("</div>");
+    templates.render_layout(pool, &embed, None).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        db,
+        models::{mashup::MashupParams, plugin::PluginParams},
+        render::plugin::{plugin_html, render_plugin},
+        storage::local::LocalStorage,
+    };
+
+    async fn plugin(pool: &SqlitePool, name: &str, params: PluginParams) -> Plugin {
+        Plugin::create(
+            pool,
+            &PluginParams {
+                name: name.to_string(),
+                ..params
+            },
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mashup_uses_markup_for_each_view() {
+        let pool = db::test_pool().await;
+        let weather = plugin(
+            &pool,
+            "Weather",
+            PluginParams {
+                render_markup: Some("<p>full {{ temp }}</p>".to_string()),
+                markup_half_vertical: Some("<p>half {{ temp }}</p>".to_string()),
+                markup_quadrant: Some("<p>quadrant {{ temp }}</p>".to_string()),
+                data_payload: Some(r#"{"temp": 21}"#.to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+        let clock = plugin(
+            &pool,
+            "Clock",
+            PluginParams {
+                render_markup: Some("<p>clock</p>".to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        let templates = TemplateCache::new("templates", false);
+        let html = mashup_html(
+            &pool,
+            &templates,
+            MashupLayout::OneLeftTwoRight,
+            &[weather.clone(), clock.clone(), weather],
+        )
+        .await
+        .unwrap();
+        assert!(html.contains(concat!(
+            r#"<div class="mashup mashup--1Lx2R">"#,
+            r#"<div class="view view--half_vertical"><p>half 21</p></div>"#,
+            r#"<div class="view view--quadrant"><p>clock</p></div>"#,
+            r#"<div class="view view--quadrant"><p>quadrant 21</p></div>"#,
+            "</div>"
+        )));
+        assert!(!html.contains("view--full"));
+
+        assert!(
+            mashup_html(&pool, &templates, MashupLayout::Quadrants, &[clock])
+                .await
+                .is_err()
+        );
+    }
+
+    /// Store a screen for `html` as if it was rendered, so the render
+    /// functions reuse it instead of starting a browser
+    async fn prerender(storage: &LocalStorage, config: &Config, html: &str) {
+        let image = RenderedImage::for_html(html, &config.render);
+        storage
+            .put(&image.png_key(), b"png".to_vec())
+            .await
+            .unwrap();
+        storage
+            .put(&image.bmp_key(), b"bmp".to_vec())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_member_update_refreshes_mashup() {
+        let pool = db::test_pool().await;
+        let config = Config::for_tests();
+        let templates = TemplateCache::new("templates", false);
+        let storage = LocalStorage::new(
+            std::env::temp_dir().join(format!("patina-mashup-{}", uuid::Uuid::new_v4())),
+        )
+        .unwrap();
+        let weather = plugin(
+            &pool,
+            "Weather",
+            PluginParams {
+                render_markup: Some("<p>{{ temp }}</p>".to_string()),
+                data_payload: Some(r#"{"temp": 21}"#.to_string()),
+                ..Default::default()
+            },
+        )
+        .await;
+        let mashup = Mashup::create(
+            &pool,
+            &MashupParams {
+                name: "Dashboard".to_string(),
+                layout: MashupLayout::OneLeftOneRight,
+                plugin_ids: vec![weather.id, weather.id],
+            },
+        )
+        .await
+        .unwrap();
+        let layout = mashup.layout().unwrap();
+
+        let html = mashup_html(
+            &pool,
+            &templates,
+            layout,
+            &[weather.clone(), weather.clone()],
+        )
+        .await
+        .unwrap();
+        prerender(&storage, &config, &html).await;
+        let before = render_mashup(&pool, &config, &templates, &storage, &mashup)
+            .await
+            .unwrap();
+
+        Plugin::update_data_payload(&pool, weather.id, r#"{"temp": 22}"#)
+            .await
+            .unwrap();
+        let weather = Plugin::find(&pool, weather.id).await.unwrap().unwrap();
+        prerender(
+            &storage,
+            &config,
+            &plugin_html(&pool, &templates, &weather).await.unwrap(),
+        )
+        .await;
+        render_plugin(&pool, &config, &templates, &storage, &weather)
+            .await
+            .unwrap();
+        let mashup = Mashup::find(&pool, mashup.id).await.unwrap().unwrap();
+        assert_eq!(mashup.current_image, None);
+
+        let html = mashup_html(&pool, &templates, layout, &[weather.clone(), weather])
+            .await
+            .unwrap();
+        prerender(&storage, &config, &html).await;
+        let after = render_mashup(&pool, &config, &templates, &storage, &mashup)
+            .await
+            .unwrap();
+        assert_ne!(after, before);
+        let mashup = Mashup::find(&pool, mashup.id).await.unwrap().unwrap();
+        assert_eq!(mashup.current_image, Some(after.id()));
+    }
+}
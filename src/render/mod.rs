@@ -1,2 +1,9 @@
+pub mod cache;
+pub mod filters;
+pub mod gc;
 pub mod image;
+pub mod mashup;
+pub mod playlist;
+pub mod plugin;
+pub mod preview;
 pub mod template;
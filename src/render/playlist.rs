@@ -0,0 +1,103 @@
+use anyhow::anyhow;
+use chrono::{Local, Utc};
+use log::info;
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    models::{
+        device::Device,
+        mashup::Mashup,
+        playlist::{Playlist, PlaylistItem},
+        plugin::Plugin,
+    },
+    render::{
+        cache::TemplateCache, image::RenderedImage, mashup::render_mashup, plugin::render_plugin,
+    },
+    storage::ImageStorage,
+};
+
+/// Move the device along its first playlist scheduled right now: pick the
+/// item that is due, render it if it has no stored image yet and make it the
+/// device's current screen. Devices without a scheduled playlist are
+/// returned unchanged.
+pub async fn advance_playlist(
+    pool: &SqlitePool,
+    config: &Config,
+    templates: &TemplateCache,
+    storage: &dyn ImageStorage,
+    device: Device,
+) -> Result<Device, anyhow::Error> {
+    let local = Local::now().naive_local();
+    for playlist in Playlist::for_device(pool, device.id).await? {
+        if !playlist.is_scheduled(local) {
+            continue;
+        }
+        let items = playlist.items(pool).await?;
+        let Some(item) = playlist.next_item(&items, Utc::now().naive_utc()) else {
+            continue;
+        };
+        let image = item_image(pool, config, templates, storage, item).await?;
+        let on_screen = items
+            .iter()
+            .filter(|item| item.last_displayed_at.is_some())
+            .max_by_key(|item| item.last_displayed_at)
+            .map(|item| item.id);
+        if on_screen != Some(item.id) {
+            PlaylistItem::mark_displayed(pool, item.id).await?;
+        }
+        if device.current_screen_image.as_deref() == Some(image.id().as_str()) {
+            return Ok(device);
+        }
+        info!(
+            "showing item {} of playlist {:?} on {}",
+            item.id, playlist.name, device.mac_address
+        );
+        return Ok(Device::set_current_screen_image(pool, device.id, &image.id()).await?);
+    }
+    Ok(device)
+}
+
+/// The stored screen of the item's plugin or mashup, rendered first when
+/// there is none
+async fn item_image(
+    pool: &SqlitePool,
+    config: &Config,
+    templates: &TemplateCache,
+    storage: &dyn ImageStorage,
+    item: &PlaylistItem,
+) -> Result<RenderedImage, anyhow::Error> {
+    if let Some(mashup_id) = item.mashup_id {
+        let mashup = Mashup::find(pool, mashup_id)
+            .await?
+            .ok_or_else(|| anyhow!("mashup {} does not exist", mashup_id))?;
+        if let Some(image) = stored(storage, mashup.current_image.as_deref()).await? {
+            return Ok(image);
+        }
+        return render_mashup(pool, config, templates, storage, &mashup).await;
+    }
+    let plugin_id = item
+        .plugin_id
+        .ok_or_else(|| anyhow!("playlist item {} has nothing to show", item.id))?;
+    let plugin = Plugin::find(pool, plugin_id)
+        .await?
+        .ok_or_else(|| anyhow!("plugin {} does not exist", plugin_id))?;
+    if let Some(image) = stored(storage, plugin.current_image.as_deref()).await? {
+        return Ok(image);
+    }
+    render_plugin(pool, config, templates, storage, &plugin).await
+}
+
+/// The image named `id` when a copy of it is still stored
+async fn stored(
+    storage: &dyn ImageStorage,
+    id: Option<&str>,
+) -> Result<Option<RenderedImage>, anyhow::Error> {
+    let Some(id) = id else {
+        return Ok(None);
+    };
+    let image = RenderedImage::from_id(id);
+    let exists =
+        storage.exists(&image.png_key()).await? || storage.exists(&image.bmp_key()).await?;
+    Ok(exists.then_some(image))
+}
@@ -0,0 +1,51 @@
+use serde_json::{Value, json};
+use sqlx::SqlitePool;
+
+use crate::{
+    config::Config,
+    models::{
+        mashup::Mashup,
+        plugin::{Plugin, View},
+    },
+    render::{cache::TemplateCache, image::RenderedImage},
+    storage::ImageStorage,
+};
+
+/// Render a plugin's markup with its current data payload and record the
+/// result as the plugin's current image. Mashups showing the plugin are
+/// rendered again when next shown if its screen changed.
+pub async fn render_plugin(
+    pool: &SqlitePool,
+    config: &Config,
+    templates: &TemplateCache,
+    storage: &dyn ImageStorage,
+    plugin: &Plugin,
+) -> Result<RenderedImage, anyhow::Error> {
+    let html = plugin_html(pool, templates, plugin).await?;
+    let image = RenderedImage::render_content(&html, config, storage).await?;
+    if plugin.current_image.as_deref() != Some(image.id().as_str()) {
+        Plugin::set_current_image(pool, plugin.id, &image.id()).await?;
+        Mashup::clear_images_showing(pool, plugin.id).await?;
+    }
+    Ok(image)
+}
+
+/// The plugin's data payload, the variables its markup is rendered with
+pub fn plugin_data(plugin: &Plugin) -> Result<Value, anyhow::Error> {
+    Ok(match plugin.data_payload.as_deref() {
+        Some(payload) => serde_json::from_str(payload)?,
+        None => json!({}),
+    })
+}
+
+/// Build the full HTML document for a plugin, in its layout, without rendering it
+pub async fn plugin_html(
+    pool: &SqlitePool,
+    templates: &TemplateCache,
+    plugin: &Plugin,
+) -> Result<String, anyhow::Error> {
+    let view = templates.render_view(pool, plugin, View::Full).await?;
+    templates
+        .render_layout(pool, &view, plugin.layout.as_deref())
+        .await
+}
@@ -0,0 +1,245 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{error, info};
+use serde_json::Value;
+
+use crate::{
+    config::Config,
+    render::{
+        image::screenshot,
+        template::{directory_partials, layout_path, render_user_template_embedded},
+    },
+};
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A template file rendered offline to a PNG/BMP pair, for iterating on markup
+/// without going through the API
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub template: PathBuf,
+    /// Inline JSON, or the path of a JSON file
+    pub data: String,
+    pub out: PathBuf,
+    /// A layout from `templates/layouts`, `base.liquid` when `None`
+    pub layout: Option<String>,
+}
+
+impl Preview {
+    /// The data file, when `data` names one rather than being inline JSON
+    fn data_path(&self) -> Option<&Path> {
+        let path = Path::new(&self.data);
+        (!self.data.trim_start().starts_with('{') && path.is_file()).then_some(path)
+    }
+
+    pub fn data(&self) -> Result<Value> {
+        let json = match self.data_path() {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+            None => self.data.clone(),
+        };
+        let value: Value = serde_json::from_str(&json).context("data is not valid JSON")?;
+        if !value.is_object() {
+            bail!("data must be a JSON object");
+        }
+        Ok(value)
+    }
+
+    pub fn html(&self, config: &Config) -> Result<String> {
+        let markup = std::fs::read_to_string(&self.template)
+            .with_context(|| format!("failed to read {}", self.template.display()))?;
+        // Offline, so only the partials in the templates directory are available
+        let templates_dir = &config.paths.templates_dir;
+        render_user_template_embedded(
+            &markup,
+            self.data()?,
+            templates_dir,
+            &directory_partials(templates_dir)?,
+            self.layout.as_deref(),
+        )
+    }
+
+    /// The BMP written alongside the PNG at `out`
+    pub fn bmp_path(&self) -> PathBuf {
+        self.out.with_extension("bmp")
+    }
+
+    pub async fn render(&self, config: &Config) -> Result<()> {
+        let html = self.html(config)?;
+        if let Some(parent) = self.out.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create {}", parent.display()))?;
+        }
+        let screen = screenshot(&html, config).await?;
+        std::fs::write(&self.out, screen.png)
+            .with_context(|| format!("failed to write {}", self.out.display()))?;
+        let bmp_path = self.bmp_path();
+        std::fs::write(&bmp_path, screen.bmp)
+            .with_context(|| format!("failed to write {}", bmp_path.display()))?;
+        Ok(())
+    }
+
+    /// Every file whose change should trigger a re-render
+    fn watched_paths(&self, config: &Config) -> Vec<PathBuf> {
+        let templates_dir = &config.paths.templates_dir;
+        let mut paths = vec![self.template.clone()];
+        paths.extend(layout_path(templates_dir, self.layout.as_deref()).ok());
+        paths.extend(self.data_path().map(Path::to_path_buf));
+        paths.extend(partial_files(&templates_dir.join("partials")));
+        paths
+    }
+
+    /// Render once, then again every time the template, data file, layout or
+    /// a partial is saved. Render errors are logged and watching continues.
+    pub async fn watch(&self, config: &Config) -> Result<()> {
+        let mut watcher = FileWatcher::new(self.watched_paths(config));
+        loop {
+            match self.render(config).await {
+                Ok(()) => info!("rendered {}", self.out.display()),
+                Err(err) => error!("render failed: {:#}", err),
+            }
+            while !watcher.changed() {
+                tokio::time::sleep(POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Detects changes by comparing modification times between polls
+struct FileWatcher {
+    paths: Vec<PathBuf>,
+    seen: Vec<Option<SystemTime>>,
+}
+
+impl FileWatcher {
+    fn new(paths: Vec<PathBuf>) -> Self {
+        let seen = paths.iter().map(|p| modified(p)).collect();
+        Self { paths, seen }
+    }
+
+    /// Whether any file was modified, created or removed since the last call
+    fn changed(&mut self) -> bool {
+        let current: Vec<_> = self.paths.iter().map(|p| modified(p)).collect();
+        let changed = current != self.seen;
+        self.seen = current;
+        changed
+    }
+}
+
+fn partial_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+    {
+        if path.is_dir() {
+            files.extend(partial_files(&path));
+        } else if path.extension().is_some_and(|ext| ext == "liquid") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("patina-preview-{}-{}", name, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_inline_and_file_data() {
+        let dir = temp_dir("data");
+        let mut preview = Preview {
+            template: dir.join("t.liquid"),
+            data: r#"{"temp": 21}"#.to_string(),
+            out: dir.join("out.png"),
+            layout: None,
+        };
+        assert_eq!(preview.data().unwrap()["temp"], 21);
+
+        let data = dir.join("data.json");
+        std::fs::write(&data, r#"{"temp": 18}"#).unwrap();
+        preview.data = data.display().to_string();
+        assert_eq!(preview.data().unwrap()["temp"], 18);
+
+        preview.data = "[1, 2]".to_string();
+        assert!(preview.data().is_err());
+        preview.data = "not json".to_string();
+        assert!(preview.data().is_err());
+    }
+
+    #[test]
+    fn test_html_embeds_template_in_layout() {
+        let dir = temp_dir("html");
+        let template = dir.join("t.liquid");
+        std::fs::write(&template, "<p>{{ temp }} degrees</p>").unwrap();
+        let preview = Preview {
+            template,
+            data: r#"{"temp": 21}"#.to_string(),
+            out: dir.join("out.png"),
+            layout: None,
+        };
+
+        let html = preview.html(&Config::for_tests()).unwrap();
+        assert!(html.contains("<p>21 degrees</p>"));
+        assert!(html.contains("<html"));
+    }
+
+    #[test]
+    fn test_watched_paths_include_data_file() {
+        let dir = temp_dir("watched");
+        let data = dir.join("data.json");
+        std::fs::write(&data, "{}").unwrap();
+        let config = Config::for_tests();
+        let mut preview = Preview {
+            template: dir.join("t.liquid"),
+            data: "{}".to_string(),
+            out: dir.join("out.png"),
+            layout: None,
+        };
+        let paths = preview.watched_paths(&config);
+        assert!(paths.contains(&PathBuf::from("templates/base.liquid")));
+        assert!(!paths.contains(&data));
+
+        preview.data = data.display().to_string();
+        assert!(preview.watched_paths(&config).contains(&data));
+    }
+
+    #[test]
+    fn test_file_watcher_detects_changes() {
+        let dir = temp_dir("watcher");
+        let file = dir.join("t.liquid");
+        std::fs::write(&file, "one").unwrap();
+        let mut watcher = FileWatcher::new(vec![file.clone(), dir.join("missing.json")]);
+        assert!(!watcher.changed());
+
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert!(watcher.changed());
+        assert!(!watcher.changed());
+
+        std::fs::write(dir.join("missing.json"), "{}").unwrap();
+        assert!(watcher.changed());
+    }
+}
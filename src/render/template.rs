@@ -1,21 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, bail};
+use liquid::partials::{InMemorySource, LazyCompiler};
 use serde_json::{Value, json};
+use sqlx::SqlitePool;
+
+use crate::{
+    models::{partial::Partial, plugin::View},
+    render::filters,
+};
+
+/// Partials available to `{% render %}` and `{% include %}`, by name
+pub type Partials = InMemorySource;
+
+/// The layout used when a plugin doesn't name one
+pub const DEFAULT_LAYOUT: &str = "base";
 
-pub fn basic_template() -> Result<String, anyhow::Error> {
+pub fn basic_template(templates_dir: &Path) -> Result<String, anyhow::Error> {
     let user_template = r#"<div class="title_bar">
         <span class="title">{{ message }}</span>
         </div>"#;
     let user_data = json!({
         "message": "hello world"
     });
-    render_user_template_embedded(user_template, user_data)
+    render_user_template_embedded(
+        user_template,
+        user_data,
+        templates_dir,
+        &Partials::new(),
+        None,
+    )
+}
+
+/// A parser with the standard library, the TRMNL filters and `partials`
+pub fn parser(partials: &Partials) -> Result<liquid::Parser, anyhow::Error> {
+    Ok(filters::register(liquid::ParserBuilder::with_stdlib())
+        .partials(LazyCompiler::new(partials.clone()))
+        .build()?)
+}
+
+/// Every `*.liquid` file under `templates_dir/partials`, named by its path
+/// relative to that directory without the extension, e.g. `weather/footer`
+pub fn directory_partials(templates_dir: &Path) -> Result<Partials, anyhow::Error> {
+    fn walk(root: &Path, dir: &Path, partials: &mut Partials) -> Result<(), anyhow::Error> {
+        let entries =
+            std::fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                walk(root, &path, partials)?;
+            } else if path.extension().is_some_and(|ext| ext == "liquid") {
+                let name = path
+                    .strip_prefix(root)?
+                    .with_extension("")
+                    .to_string_lossy()
+                    .replace(std::path::MAIN_SEPARATOR, "/");
+                let markup = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                partials.add(name, markup);
+            }
+        }
+        Ok(())
+    }
+
+    let mut partials = Partials::new();
+    let root = templates_dir.join("partials");
+    if root.is_dir() {
+        walk(&root, &root, &mut partials)?;
+    }
+    Ok(partials)
+}
+
+/// Directory partials plus those stored in the database, which take
+/// precedence when the names collide
+pub async fn load_partials(
+    pool: &SqlitePool,
+    templates_dir: &Path,
+) -> Result<Partials, anyhow::Error> {
+    let mut partials = directory_partials(templates_dir)?;
+    for partial in Partial::all(pool).await? {
+        partials.add(partial.name, partial.markup);
+    }
+    Ok(partials)
+}
+
+/// Names of the layouts a plugin can use: `base` and every file in
+/// `templates_dir/layouts`
+pub fn layout_names(templates_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(templates_dir.join("layouts"))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "liquid"))
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().to_string()))
+        .filter(|name| valid_layout_name(name))
+        .collect();
+    names.sort();
+    names.insert(0, DEFAULT_LAYOUT.to_string());
+    names
+}
+
+fn valid_layout_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// The file for a named layout, `None` meaning `base.liquid`
+pub fn layout_path(templates_dir: &Path, layout: Option<&str>) -> Result<PathBuf, anyhow::Error> {
+    match layout {
+        None | Some(DEFAULT_LAYOUT) => Ok(templates_dir.join("base.liquid")),
+        Some(name) if valid_layout_name(name) => {
+            let path = templates_dir
+                .join("layouts")
+                .join(format!("{}.liquid", name));
+            if !path.is_file() {
+                bail!("unknown layout {:?}", name);
+            }
+            Ok(path)
+        }
+        Some(name) => bail!("invalid layout name {:?}", name),
+    }
 }
 
 /// Renders a user template with the provided data.
 pub fn render_user_template(
     user_template: &str,
     user_data: Value,
+    partials: &Partials,
 ) -> Result<String, anyhow::Error> {
-    let parser = liquid::ParserBuilder::with_stdlib().build()?;
+    let parser = parser(partials)?;
     let template = parser.parse(user_template)?;
     let obj = liquid::to_object(&user_data)?;
 
@@ -24,16 +139,45 @@ pub fn render_user_template(
     Ok(out)
 }
 
-pub fn render_user_template_embedded(
+/// Renders a template file from disk with the provided data.
+pub fn render_template_file(path: &Path, data: Value) -> Result<String, anyhow::Error> {
+    let parser = parser(&Partials::new())?;
+    let template = parser.parse_file(path)?;
+    let obj = liquid::to_object(&data)?;
+
+    let out = template.render(&obj)?;
+
+    Ok(out)
+}
+
+/// Renders a user template into the framework's container for `view`
+pub fn render_view(
     user_template: &str,
     user_data: Value,
+    partials: &Partials,
+    view: View,
 ) -> Result<String, anyhow::Error> {
-    let user_defined = render_user_template(user_template, user_data)?;
+    let content = render_user_template(user_template, user_data, partials)?;
+    Ok(view_container(&content, view))
+}
+
+/// Wraps rendered markup in the framework's container for `view`
+pub fn view_container(content: &str, view: View) -> String {
+    format!(r#"<div class="{}">{}</div>"#, view.class(), content)
+}
 
-    let parser = liquid::ParserBuilder::with_stdlib().build()?;
-    let base = parser.parse_file("./templates/base.liquid")?;
+/// Renders a layout from `templates_dir`, `base.liquid` unless another is
+/// named. Layouts receive the screen's views as `embed` and can use partials too.
+pub fn render_layout(
+    embed: &str,
+    templates_dir: &Path,
+    partials: &Partials,
+    layout: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let parser = parser(partials)?;
+    let base = parser.parse_file(layout_path(templates_dir, layout)?)?;
     let data = json!({
-        "embed": user_defined
+        "embed": embed
     });
     let obj = liquid::to_object(&data)?;
 
@@ -42,11 +186,48 @@ pub fn render_user_template_embedded(
     Ok(out)
 }
 
+/// Renders a user template as a full screen view inside a layout
+pub fn render_user_template_embedded(
+    user_template: &str,
+    user_data: Value,
+    templates_dir: &Path,
+    partials: &Partials,
+    layout: Option<&str>,
+) -> Result<String, anyhow::Error> {
+    let view = render_view(user_template, user_data, partials, View::Full)?;
+    render_layout(&view, templates_dir, partials, layout)
+}
+
 #[cfg(test)]
 mod tests {
     use serde_json::json;
 
     use super::*;
+    use crate::render::image::FRAMEWORK_HOSTS;
+    use crate::{db, models::partial::PartialParams};
+
+    fn templates_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("patina-templates-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("partials/weather")).unwrap();
+        std::fs::create_dir_all(dir.join("layouts")).unwrap();
+        std::fs::write(dir.join("base.liquid"), "<main>{{ embed }}</main>").unwrap();
+        std::fs::write(
+            dir.join("layouts/framed.liquid"),
+            r#"<div class="frame">{{ embed }}{% render "footer" %}</div>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("partials/footer.liquid"),
+            "<footer>patina</footer>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("partials/weather/title.liquid"),
+            "<h1>{{ title }}</h1>",
+        )
+        .unwrap();
+        dir
+    }
 
     #[test]
     fn test_render_user_template() {
@@ -56,7 +237,84 @@ mod tests {
             "message": "hello world"
         });
 
-        let rendered = render_user_template(user_template, user_data).unwrap();
+        let rendered = render_user_template(user_template, user_data, &Partials::new()).unwrap();
         assert_eq!(rendered, "<pi>hello world</p>");
     }
+
+    #[test]
+    fn test_render_and_include_partials() {
+        let partials = directory_partials(&templates_dir()).unwrap();
+
+        let rendered = render_user_template(
+            r#"{% render "weather/title", title: city %}{% include "footer" %}"#,
+            json!({ "city": "Oslo" }),
+            &partials,
+        )
+        .unwrap();
+        assert_eq!(rendered, "<h1>Oslo</h1><footer>patina</footer>");
+
+        assert!(render_user_template(r#"{% render "missing" %}"#, json!({}), &partials).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_database_partials_override_directory() {
+        let pool = db::test_pool().await;
+        let dir = templates_dir();
+        Partial::create(
+            &pool,
+            &PartialParams {
+                name: "footer".to_string(),
+                markup: "<footer>{{ 'custom' | upcase }}</footer>".to_string(),
+            },
+        )
+        .await
+        .unwrap();
+
+        let partials = load_partials(&pool, &dir).await.unwrap();
+        let rendered = render_user_template(r#"{% render "footer" %}"#, json!({}), &partials);
+        assert_eq!(rendered.unwrap(), "<footer>CUSTOM</footer>");
+    }
+
+    #[test]
+    fn test_base_layout_loads_framework_hosts_only() {
+        let base = std::fs::read_to_string("templates/base.liquid").unwrap();
+        let urls: Vec<&str> = base
+            .split('"')
+            .filter(|part| part.starts_with("https://"))
+            .collect();
+        assert!(!urls.is_empty());
+        for url in urls {
+            let host = reqwest::Url::parse(url)
+                .unwrap()
+                .host_str()
+                .unwrap()
+                .to_string();
+            assert!(FRAMEWORK_HOSTS.contains(&host.as_str()), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_named_layouts() {
+        let dir = templates_dir();
+        let partials = directory_partials(&dir).unwrap();
+
+        let base =
+            render_user_template_embedded("<p>hi</p>", json!({}), &dir, &partials, None).unwrap();
+        assert_eq!(
+            base,
+            r#"<main><div class="view view--full"><p>hi</p></div></main>"#
+        );
+
+        let framed =
+            render_user_template_embedded("<p>hi</p>", json!({}), &dir, &partials, Some("framed"))
+                .unwrap();
+        assert_eq!(
+            framed,
+            r#"<div class="frame"><div class="view view--full"><p>hi</p></div><footer>patina</footer></div>"#
+        );
+
+        assert_eq!(layout_names(&dir), vec!["base", "framed"]);
+        assert!(layout_path(&dir, Some("missing")).is_err());
+        assert!(layout_path(&dir, Some("../base")).is_err());
+    }
 }
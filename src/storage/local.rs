@@ -0,0 +1,158 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{Context, bail};
+use async_trait::async_trait;
+
+use crate::storage::{ImageStorage, StoredObject, valid_key};
+
+/// Images kept as files in one directory
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    /// Storage in `dir`, created when missing
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self, anyhow::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, anyhow::Error> {
+        if !valid_key(key) {
+            bail!("invalid image key {:?}", key);
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+#[async_trait]
+impl ImageStorage for LocalStorage {
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), anyhow::Error> {
+        let path = self.path(key)?;
+        // Write then rename so readers never see a partial image. Concurrent
+        // renders of the same screen each write their own file.
+        let partial = self
+            .dir
+            .join(format!(".{}.{}.partial", key, uuid::Uuid::new_v4()));
+        let written = match tokio::fs::write(&partial, bytes).await {
+            Ok(()) => tokio::fs::rename(&partial, &path).await,
+            Err(err) => Err(err),
+        };
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        written.with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, anyhow::Error> {
+        Ok(tokio::fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn touch(&self, key: &str) -> Result<(), anyhow::Error> {
+        let path = self.path(key)?;
+        tokio::task::spawn_blocking(move || {
+            let file = std::fs::File::options().write(true).open(path)?;
+            file.set_modified(SystemTime::now())
+        })
+        .await??;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<StoredObject>, anyhow::Error> {
+        let mut objects = Vec::new();
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .with_context(|| format!("failed to read {}", self.dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let key = entry.file_name().to_string_lossy().to_string();
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() || !valid_key(&key) {
+                continue;
+            }
+            objects.push(StoredObject {
+                key,
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+        Ok(objects)
+    }
+
+    fn location(&self, key: &str) -> String {
+        self.dir.join(key).display().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("patina-storage-{}", uuid::Uuid::new_v4()));
+        let storage = LocalStorage::new(&dir).unwrap();
+
+        assert!(!storage.exists("a.png").await.unwrap());
+        assert_eq!(storage.get("a.png").await.unwrap(), None);
+        storage.put("a.png", b"image".to_vec()).await.unwrap();
+        assert!(storage.exists("a.png").await.unwrap());
+        assert_eq!(storage.get("a.png").await.unwrap(), Some(b"image".to_vec()));
+        storage.touch("a.png").await.unwrap();
+
+        let objects = storage.list().await.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!((objects[0].key.as_str(), objects[0].size), ("a.png", 5));
+        assert_eq!(
+            storage.location("a.png"),
+            dir.join("a.png").display().to_string()
+        );
+
+        storage.delete("a.png").await.unwrap();
+        storage.delete("a.png").await.unwrap();
+        assert!(storage.list().await.unwrap().is_empty());
+        assert!(storage.put("../escape.png", Vec::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_puts_of_one_key() {
+        let dir = std::env::temp_dir().join(format!("patina-storage-{}", uuid::Uuid::new_v4()));
+        let storage = std::sync::Arc::new(LocalStorage::new(&dir).unwrap());
+
+        let writes: Vec<_> = (0..8u8)
+            .map(|i| {
+                let storage = storage.clone();
+                tokio::spawn(async move { storage.put("a.png", vec![i; 64 * 1024]).await })
+            })
+            .collect();
+        for write in writes {
+            write.await.unwrap().unwrap();
+        }
+
+        let bytes = storage.get("a.png").await.unwrap().unwrap();
+        assert_eq!(bytes.len(), 64 * 1024);
+        assert!(bytes.iter().all(|b| *b == bytes[0]));
+        // No temporary files left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+}
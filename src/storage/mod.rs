@@ -0,0 +1,134 @@
+use std::{sync::Arc, time::SystemTime};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Path, State},
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
+use log::warn;
+
+use crate::{config::Config, models::state::AppState};
+
+pub mod local;
+pub mod s3;
+pub mod signing;
+
+/// Where rendered screens are kept. Keys are plain file names such as
+/// `<id>.png`.
+#[async_trait]
+pub trait ImageStorage: Send + Sync {
+    /// Store `bytes` under `key`, replacing any existing object
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> Result<(), anyhow::Error>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, anyhow::Error>;
+    async fn exists(&self, key: &str) -> Result<bool, anyhow::Error>;
+    /// Mark an object as just written, so cleanup treats it as fresh
+    async fn touch(&self, key: &str) -> Result<(), anyhow::Error>;
+    async fn delete(&self, key: &str) -> Result<(), anyhow::Error>;
+    async fn list(&self) -> Result<Vec<StoredObject>, anyhow::Error>;
+    /// Where `key` lives, for logs and command output
+    fn location(&self, key: &str) -> String;
+    /// A URL devices can download `key` from directly instead of through
+    /// this server
+    fn public_url(&self, _key: &str) -> Option<String> {
+        None
+    }
+}
+
+/// Shared handle to the configured backend
+pub type Storage = Arc<dyn ImageStorage>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+pub fn from_config(config: &Config) -> Result<Storage, anyhow::Error> {
+    Ok(match config.storage.backend.as_str() {
+        "s3" => Arc::new(s3::S3Storage::new(&config.storage.s3)?),
+        _ => Arc::new(local::LocalStorage::new(&config.paths.generated_dir)?),
+    })
+}
+
+/// Keys are single file names, never paths
+pub fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && !key.starts_with('.')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+pub fn content_type(key: &str) -> &'static str {
+    match key.rsplit('.').next() {
+        Some("png") => "image/png",
+        Some("bmp") => "image/bmp",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Serve a rendered image from storage
+pub async fn serve_image(
+    Path(key): Path<String>,
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if !valid_key(&key) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let bytes = state
+        .storage
+        .get(&key)
+        .await
+        .map_err(|err| {
+            warn!("failed to read image {}: {:#}", key, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+    Ok(([(header::CONTENT_TYPE, content_type(&key))], bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use axum::{Router, body::Body, http::Request, routing::get};
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_valid_keys() {
+        assert!(valid_key("0a1b.png"));
+        assert!(!valid_key("../database.db"));
+        assert!(!valid_key(".render-1.html"));
+        assert!(!valid_key("a/b.png"));
+        assert!(!valid_key(""));
+    }
+
+    #[tokio::test]
+    async fn test_serve_image_from_storage() {
+        let state = AppState::for_tests(db::test_pool().await);
+        state.storage.put("abc.png", b"png".to_vec()).await.unwrap();
+        let app = Router::new()
+            .route("/images/{key}", get(serve_image))
+            .with_state(state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/images/abc.png").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+
+        let response = app
+            .oneshot(
+                Request::get("/images/missing.png")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
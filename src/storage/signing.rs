@@ -0,0 +1,197 @@
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, Query, Request, State, rejection::QueryRejection},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use hmac::{Hmac, Mac};
+use log::{debug, warn};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{config::Config, models::state::AppState};
+
+/// Signs links to generated images so a screen can only be downloaded by
+/// whoever was handed its URL, and only until the link expires
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Arc<[u8]>,
+}
+
+/// The query string added to a signed image URL
+#[derive(Deserialize, Debug)]
+pub struct Signature {
+    pub expires: u64,
+    pub signature: String,
+}
+
+impl UrlSigner {
+    pub fn new(key: &[u8]) -> Self {
+        UrlSigner { key: key.into() }
+    }
+
+    /// Signer using `image_signing_key`, or a random key that only lasts
+    /// until the server restarts
+    pub fn from_config(config: &Config) -> Self {
+        match &config.image_signing_key {
+            Some(key) => UrlSigner::new(key.as_bytes()),
+            None => {
+                warn!(
+                    "image_signing_key is not set, image links will stop working after a restart"
+                );
+                UrlSigner::new(&rand::random::<[u8; 32]>())
+            }
+        }
+    }
+
+    fn mac(&self, key: &str, expires: u64) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(format!("{}\n{}", key, expires).as_bytes());
+        mac
+    }
+
+    fn signature(&self, key: &str, expires: u64) -> String {
+        self.mac(key, expires)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The query string granting access to the image `key` for `ttl`
+    pub fn sign(&self, key: &str, ttl: Duration) -> String {
+        let expires = unix_time() + ttl.as_secs();
+        format!(
+            "expires={}&signature={}",
+            expires,
+            self.signature(key, expires)
+        )
+    }
+
+    /// Whether `signature` was made for `key` and hasn't expired yet
+    pub fn verify(&self, key: &str, signature: &Signature) -> bool {
+        if signature.expires < unix_time() {
+            return false;
+        }
+        match decode_hex(&signature.signature) {
+            Some(bytes) => self
+                .mac(key, signature.expires)
+                .verify_slice(&bytes)
+                .is_ok(),
+            None => false,
+        }
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Reject requests for generated images without a valid, unexpired signature
+pub async fn require_signature(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    signature: Result<Query<Signature>, QueryRejection>,
+    request: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    match signature {
+        Ok(Query(signature)) if state.signer.verify(&key, &signature) => {
+            Ok(next.run(request).await)
+        }
+        _ => {
+            debug!("refused unsigned or expired request for image {}", key);
+            Err(StatusCode::FORBIDDEN)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{db, storage::serve_image};
+    use axum::{Router, body::Body, middleware, routing::get};
+    use tower::ServiceExt;
+
+    fn parse(query: &str) -> Signature {
+        let uri = format!("/?{}", query).parse().unwrap();
+        Query::<Signature>::try_from_uri(&uri).unwrap().0
+    }
+
+    #[test]
+    fn test_verify() {
+        let signer = UrlSigner::new(b"secret");
+        let signed = parse(&signer.sign("abc.png", Duration::from_secs(15)));
+        assert!(signer.verify("abc.png", &signed));
+        assert!(!signer.verify("abd.png", &signed));
+        assert!(!UrlSigner::new(b"other").verify("abc.png", &signed));
+
+        let forged = Signature {
+            expires: signed.expires + 60,
+            signature: signed.signature.clone(),
+        };
+        assert!(!signer.verify("abc.png", &forged));
+        let expires = unix_time() - 1;
+        let expired = Signature {
+            expires,
+            signature: signer.signature("abc.png", expires),
+        };
+        assert!(!signer.verify("abc.png", &expired));
+    }
+
+    #[tokio::test]
+    async fn test_middleware_requires_signature() {
+        let state = AppState::for_tests(db::test_pool().await);
+        state.storage.put("abc.png", b"png".to_vec()).await.unwrap();
+        let query = state.signer.sign("abc.png", Duration::from_secs(15));
+        let app = Router::new()
+            .route(
+                "/images/{key}",
+                get(serve_image).route_layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_signature,
+                )),
+            )
+            .with_state(state);
+
+        for (uri, status) in [
+            (format!("/images/abc.png?{}", query), StatusCode::OK),
+            ("/images/abc.png".to_string(), StatusCode::FORBIDDEN),
+            (
+                "/images/abc.png?expires=1&signature=00".to_string(),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                format!("/images/other.png?{}", query),
+                StatusCode::FORBIDDEN,
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::get(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", uri);
+        }
+    }
+}